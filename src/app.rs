@@ -1,7 +1,11 @@
+use crate::llm::preset::DEFAULT_PRESET;
+use crate::llm::{usage, Attachment, ToolRequest, LLM};
 use crate::ui::file_explore::FileExplorer;
-use crate::ui::{Chat, Formatter, Help, History, Notification, Prompt, Spinner};
+use crate::ui::{
+    BackendPicker, Chat, Formatter, Help, History, ModelPicker, Notification, PresetPicker, Prompt,
+    PromptQueue, Spinner,
+};
 use std;
-use std::sync::atomic::AtomicBool;
 
 use crate::config::Config;
 use arboard::Clipboard;
@@ -9,6 +13,7 @@ use crossterm::event::KeyCode;
 use ratatui::text::Line;
 
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 
 use crate::set_language;
 
@@ -23,6 +28,10 @@ pub enum FocusedBlock {
     Help,
     FileExplorer,
     FileExplorerPreview,
+    BackendPicker,
+    ModelPicker,
+    PresetPicker,
+    Queue,
 }
 
 pub struct App<'a> {
@@ -34,9 +43,27 @@ pub struct App<'a> {
     pub file_explorer: FileExplorer,
     pub notifications: Vec<Notification>,
     pub spinner: Spinner,
-    pub terminate_response_signal: Arc<AtomicBool>,
+    /// Cancels the answer being streamed, replaced for every request.
+    pub cancel_request: CancellationToken,
+    /// An answer is streaming, new prompts wait in `queue`.
+    pub answering: bool,
+    /// Identifies the answer being streamed, events of abandoned ones are dropped.
+    pub answer_id: u64,
+    pub queue: PromptQueue,
+    /// Images sent along with the next prompt.
+    pub attachments: Vec<Attachment>,
+    /// Tool call of the answer waiting for `y` or `n`.
+    pub pending_tool: Option<ToolRequest>,
     pub clipboard: Option<Clipboard>,
     pub help: Help,
+    pub backend_picker: BackendPicker,
+    pub model_picker: ModelPicker,
+    pub preset_picker: PresetPicker,
+    pub llm_profile: String,
+    pub llm_name: String,
+    pub llm_model: String,
+    /// Context window of the active model, when known.
+    pub context_limit: Option<u64>,
     pub previous_key: KeyCode,
     pub config: Arc<Config>,
     pub formatter: &'a Formatter<'a>,
@@ -51,21 +78,54 @@ impl<'a> App<'a> {
         Self {
             running: true,
             prompt: Prompt::default(),
-            chat: Chat::new(),
+            chat: Chat::new(config.default_preset.as_deref().unwrap_or(DEFAULT_PRESET)),
             focused_block: FocusedBlock::Prompt,
             history: History::new(),
             file_explorer: FileExplorer::new(&config.file_explorer_path),
             notifications: Vec::new(),
             spinner: Spinner::default(),
-            terminate_response_signal: Arc::new(AtomicBool::new(false)),
+            cancel_request: CancellationToken::new(),
+            answering: false,
+            answer_id: 0,
+            queue: PromptQueue::new(),
+            attachments: Vec::new(),
+            pending_tool: None,
             clipboard: Clipboard::new().ok(),
             help: Help::new(),
+            backend_picker: BackendPicker::new(config.profiles.clone()),
+            model_picker: ModelPicker::new(),
+            preset_picker: PresetPicker::new(),
+            llm_profile: config.default_profile().name.clone(),
+            llm_name: String::new(),
+            llm_model: String::new(),
+            context_limit: None,
             previous_key: KeyCode::Null,
             config,
             formatter,
         }
     }
 
+    /// Records which profile is active, for the title bar and the backend picker.
+    pub fn set_llm(&mut self, profile: &str, llm: &dyn LLM) {
+        self.llm_profile = profile.to_string();
+        self.llm_name = llm.name();
+        self.llm_model = llm.model().to_string();
+        self.context_limit = self
+            .config
+            .profile(profile)
+            .and_then(|p| p.context_window())
+            .or_else(|| usage::context_limit(llm.model()));
+    }
+
+    /// The active profile, followed by its backend and model when the name doesn't say it.
+    pub fn llm_label(&self) -> String {
+        if self.llm_name.starts_with(&self.llm_profile) {
+            self.llm_name.clone()
+        } else {
+            format!("{} ({})", self.llm_profile, self.llm_name)
+        }
+    }
+
     pub fn tick(&mut self) {
         self.notifications.retain(|n| n.ttl > 0);
         self.notifications.iter_mut().for_each(|n| n.ttl -= 1);
@@ -4,8 +4,11 @@ use toml;
 use dirs;
 use serde::Deserialize;
 
+use std::collections::BTreeMap;
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::info;
 
 #[derive(Deserialize, Debug)]
@@ -16,8 +19,25 @@ pub struct Config {
     #[serde(default)]
     pub key_bindings: KeyBindings,
 
-    #[serde(default = "default_llm_backend")]
-    pub llm: LLMBackend,
+    /// Single-backend selection kept for older configs, names the profile of that backend.
+    pub llm: Option<LLMBackend>,
+
+    /// Name of the profile used at startup.
+    pub default_profile: Option<String>,
+
+    /// Prompt preset of new conversations, `default` when unset.
+    pub default_preset: Option<String>,
+
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+
+    /// Sampling settings shared by every backend, each backend section may override them.
+    #[serde(default)]
+    pub generation: GenerationConfig,
+
+    /// How long conversations are fitted into the context window, each profile may override it.
+    #[serde(default)]
+    pub context: ContextConfig,
 
     #[serde(default)]
     pub chatgpt: ChatGPTConfig,
@@ -25,11 +45,17 @@ pub struct Config {
     #[serde(default)]
     pub chatglm: ChatGLMConfig,
 
-    // pub llamacpp: Option<LLamacppConfig>,
+    #[serde(default)]
+    pub anthropic: AnthropicConfig,
+
+    pub llamacpp: Option<LlamaCppConfig>,
+
     pub ollama: Option<OllamaConfig>,
 
     pub infinilm: Option<InfiniLMConfig>,
 
+    pub mock: Option<MockConfig>,
+
     // pub file_explore_path
     #[serde(default = "default_file_root")]
     pub file_explorer_path: String,
@@ -50,10 +76,181 @@ pub fn default_llm_backend() -> LLMBackend {
     LLMBackend::ChatGLM
 }
 
+// Profiles
+/// A named backend endpoint, several profiles may use the same backend kind.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub backend: LLMBackend,
+    pub url: Option<String>,
+    pub model: Option<String>,
+    pub api_key: Option<String>,
+    /// Context window in tokens, for models the status bar doesn't know.
+    pub context_window: Option<u64>,
+    /// Lets the model call the built-in tools on `file_explorer_path`.
+    #[serde(default)]
+    pub tools: bool,
+    /// Proxy, timeouts and TLS of the HTTP client, the `network` table.
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(flatten)]
+    pub generation: GenerationConfig,
+
+    #[serde(flatten)]
+    pub context: ContextConfig,
+
+    #[serde(flatten)]
+    pub llamacpp: LlamaCppOptions,
+
+    #[serde(flatten)]
+    pub openai: OpenAIOptions,
+
+    #[serde(flatten)]
+    pub ollama: OllamaOptions,
+
+    #[serde(flatten)]
+    pub infinilm: InfiniLMOptions,
+
+    #[serde(flatten)]
+    pub chatglm: ChatGLMOptions,
+
+    #[serde(flatten)]
+    pub mock: MockOptions,
+}
+
+impl Profile {
+    fn legacy(backend: LLMBackend, url: &str, model: Option<&str>) -> Self {
+        Self {
+            name: backend.to_string(),
+            backend,
+            url: Some(url.to_string()),
+            model: model.map(String::from),
+            api_key: None,
+            context_window: None,
+            tools: false,
+            network: NetworkConfig::default(),
+            generation: GenerationConfig::default(),
+            context: ContextConfig::default(),
+            llamacpp: LlamaCppOptions::default(),
+            openai: OpenAIOptions::default(),
+            ollama: OllamaOptions::default(),
+            infinilm: InfiniLMOptions::default(),
+            chatglm: ChatGLMOptions::default(),
+            mock: MockOptions::default(),
+        }
+    }
+
+    /// Context window of the model: `context_window`, or the `num_ctx` Ollama loads it with.
+    pub fn context_window(&self) -> Option<u64> {
+        self.context_window.or(self.ollama.num_ctx.map(u64::from))
+    }
+}
+
 fn default_file_root() -> String {
     "./files/".to_string()
 }
 
+// Network
+/// HTTP client settings of a profile, built once per backend.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct NetworkConfig {
+    /// Proxy url, or `"none"` to connect directly. When unset the
+    /// `HTTP(S)_PROXY` environment variables are used, except by the local
+    /// InfiniLM and llama.cpp backends.
+    pub proxy: Option<String>,
+    /// Seconds to establish a connection.
+    pub connect_timeout: Option<u64>,
+    /// Seconds without any data from the server before an answer is given up.
+    pub read_timeout: Option<u64>,
+    /// PEM file of extra root certificates, e.g. the CA of an internal gateway.
+    pub ca_bundle: Option<PathBuf>,
+    /// Accepts any certificate, for self-signed gateways.
+    #[serde(default)]
+    pub insecure: bool,
+    #[serde(default)]
+    pub http: HttpVersion,
+}
+
+impl NetworkConfig {
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.map(Duration::from_secs)
+    }
+}
+
+/// HTTP version of the requests.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// HTTP/2 when the server offers it over TLS, HTTP/1.1 otherwise.
+    #[default]
+    Auto,
+    Http1,
+    /// HTTP/2 without negotiation, also over plain `http://` urls. Servers
+    /// that only speak HTTP/1.1 can't be reached.
+    H2c,
+}
+
+// Generation
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GenerationConfig {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<u32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub seed: Option<i64>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+}
+
+impl GenerationConfig {
+    /// Returns these settings, taking every unset value from `defaults`.
+    pub fn or(&self, defaults: &GenerationConfig) -> GenerationConfig {
+        GenerationConfig {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            top_k: self.top_k.or(defaults.top_k),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            stop: self.stop.clone().or_else(|| defaults.stop.clone()),
+            seed: self.seed.or(defaults.seed),
+            presence_penalty: self.presence_penalty.or(defaults.presence_penalty),
+            frequency_penalty: self.frequency_penalty.or(defaults.frequency_penalty),
+        }
+    }
+}
+
+// Context
+/// What is left out when a conversation no longer fits the context window.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextStrategy {
+    /// Drop the oldest turns until the request fits.
+    #[default]
+    Truncate,
+    /// Only send the last `window_messages` messages, and drop more if they still don't fit.
+    Window,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ContextConfig {
+    pub context_strategy: Option<ContextStrategy>,
+    /// Tokens kept free for the answer, `max_tokens` when unset.
+    pub reserve_tokens: Option<u64>,
+    pub window_messages: Option<usize>,
+}
+
+impl ContextConfig {
+    /// Returns these settings, taking every unset value from `defaults`.
+    pub fn or(&self, defaults: &ContextConfig) -> ContextConfig {
+        ContextConfig {
+            context_strategy: self.context_strategy.or(defaults.context_strategy),
+            reserve_tokens: self.reserve_tokens.or(defaults.reserve_tokens),
+            window_messages: self.window_messages.or(defaults.window_messages),
+        }
+    }
+}
+
 // ChatGLM
 #[derive(Deserialize, Debug, Clone)]
 pub struct ChatGLMConfig {
@@ -64,6 +261,15 @@ pub struct ChatGLMConfig {
 
     #[serde(default = "ChatGLMConfig::default_url")]
     pub url: String,
+
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(flatten)]
+    pub generation: GenerationConfig,
+
+    #[serde(flatten)]
+    pub options: ChatGLMOptions,
 }
 
 impl Default for ChatGLMConfig {
@@ -72,11 +278,26 @@ impl Default for ChatGLMConfig {
             chatglm_api_key: None,
             model: Self::default_model(),
             url: Self::default_url(),
+            network: NetworkConfig::default(),
+            generation: GenerationConfig::default(),
+            options: ChatGLMOptions::default(),
         }
     }
 }
 
 impl ChatGLMConfig {
+    fn profile(&self) -> Profile {
+        Profile {
+            api_key: env::var("CHATML_API_KEY")
+                .ok()
+                .or_else(|| self.chatglm_api_key.clone()),
+            generation: self.generation.clone(),
+            chatglm: self.options.clone(),
+            network: self.network.clone(),
+            ..Profile::legacy(LLMBackend::ChatGLM, &self.url, Some(&self.model))
+        }
+    }
+
     pub fn default_model() -> String {
         String::from("glm-4")
     }
@@ -86,6 +307,20 @@ impl ChatGLMConfig {
     }
 }
 
+/// ChatGLM token settings of a profile. Requests are signed with JWTs made from
+/// the `id.secret` api keys.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ChatGLMOptions {
+    /// More keys, used in turn with `api_key` to spread the requests.
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// Lifetime of a signed token in seconds, one hour when unset.
+    pub token_ttl: Option<u64>,
+    /// NTP server correcting the local clock, e.g. `ntp.aliyun.com`. The local
+    /// clock is used when unset or when the server can't be reached.
+    pub ntp_server: Option<String>,
+}
+
 // ChatGPT
 #[derive(Deserialize, Debug, Clone)]
 pub struct ChatGPTConfig {
@@ -96,6 +331,12 @@ pub struct ChatGPTConfig {
 
     #[serde(default = "ChatGPTConfig::default_url")]
     pub url: String,
+
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(flatten)]
+    pub generation: GenerationConfig,
 }
 
 impl Default for ChatGPTConfig {
@@ -104,11 +345,24 @@ impl Default for ChatGPTConfig {
             openai_api_key: None,
             model: Self::default_model(),
             url: Self::default_url(),
+            network: NetworkConfig::default(),
+            generation: GenerationConfig::default(),
         }
     }
 }
 
 impl ChatGPTConfig {
+    fn profile(&self) -> Profile {
+        Profile {
+            api_key: env::var("OPENAI_API_KEY")
+                .ok()
+                .or_else(|| self.openai_api_key.clone()),
+            generation: self.generation.clone(),
+            network: self.network.clone(),
+            ..Profile::legacy(LLMBackend::ChatGPT, &self.url, Some(&self.model))
+        }
+    }
+
     pub fn default_model() -> String {
         String::from("gpt-3.5-turbo")
     }
@@ -118,24 +372,325 @@ impl ChatGPTConfig {
     }
 }
 
+// Anthropic
+#[derive(Deserialize, Debug, Clone)]
+pub struct AnthropicConfig {
+    pub anthropic_api_key: Option<String>,
+
+    #[serde(default = "AnthropicConfig::default_model")]
+    pub model: String,
+
+    #[serde(default = "AnthropicConfig::default_url")]
+    pub url: String,
+
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(flatten)]
+    pub generation: GenerationConfig,
+}
+
+impl Default for AnthropicConfig {
+    fn default() -> Self {
+        Self {
+            anthropic_api_key: None,
+            model: Self::default_model(),
+            url: Self::default_url(),
+            network: NetworkConfig::default(),
+            generation: GenerationConfig::default(),
+        }
+    }
+}
+
+impl AnthropicConfig {
+    fn profile(&self) -> Profile {
+        Profile {
+            api_key: env::var("ANTHROPIC_API_KEY")
+                .ok()
+                .or_else(|| self.anthropic_api_key.clone()),
+            generation: self.generation.clone(),
+            network: self.network.clone(),
+            ..Profile::legacy(LLMBackend::Anthropic, &self.url, Some(&self.model))
+        }
+    }
+
+    pub fn default_model() -> String {
+        String::from("claude-3-5-sonnet-20240620")
+    }
+
+    pub fn default_url() -> String {
+        String::from("https://api.anthropic.com/v1/messages")
+    }
+}
+
+/// Request customisation for OpenAI-style gateways, used by `openai_compatible` profiles.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OpenAIOptions {
+    /// Sent with every request, e.g. a gateway specific auth header.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Appended to the url, e.g. `api-version` for Azure.
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    /// Merged into the request body, overriding the generated fields.
+    pub extra_body: Option<serde_json::Value>,
+}
+
 // Ollama
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct OllamaConfig {
     pub url: String,
-    pub model: String,
+    /// The first model of the server when unset.
+    pub model: Option<String>,
+
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(flatten)]
+    pub generation: GenerationConfig,
+
+    #[serde(flatten)]
+    pub options: OllamaOptions,
+}
+
+/// Ollama specific settings of a profile.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OllamaOptions {
+    /// Context length the model is loaded with.
+    pub num_ctx: Option<u32>,
+    /// Layers offloaded to the GPU, 0 runs on the CPU only.
+    pub num_gpu: Option<i32>,
+    /// How long the model stays loaded after a request, e.g. `"10m"`, or seconds, `-1` for ever.
+    pub keep_alive: Option<toml::Value>,
+}
+
+impl OllamaConfig {
+    fn profile(&self) -> Profile {
+        Profile {
+            generation: self.generation.clone(),
+            ollama: self.options.clone(),
+            network: self.network.clone(),
+            ..Profile::legacy(LLMBackend::Ollama, &self.url, self.model.as_deref())
+        }
+    }
+
+    pub fn default_url() -> String {
+        String::from("http://localhost:11434/api/chat")
+    }
 }
 
 // InfiniLM
 #[derive(Deserialize, Debug, Clone)]
 pub struct InfiniLMConfig {
     pub url: String,
-    // pub model: String,
+
+    /// Only used to label the backend, InfiniLM serves a single model.
+    pub model: Option<String>,
+
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(flatten)]
+    pub generation: GenerationConfig,
+
+    #[serde(flatten)]
+    pub options: InfiniLMOptions,
+}
+
+/// InfiniLM service settings of a profile. The profile's `url` is the `/infer` endpoint.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct InfiniLMOptions {
+    /// Keeps the conversation in server-side sessions, so only new messages are
+    /// sent and the KV cache is reused. On unless set to `false`.
+    pub sessions: Option<bool>,
+    /// `/fork` next to the `/infer` url when unset.
+    pub fork_url: Option<String>,
+    /// `/drop` next to the `/infer` url when unset.
+    pub drop_url: Option<String>,
+    /// Names of the request fields, for services that renamed them.
+    #[serde(default)]
+    pub payload_keys: InfiniLMKeys,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct InfiniLMKeys {
+    pub inputs: String,
+    pub session_id: String,
+    pub new_session_id: String,
+    pub dialog_pos: String,
+    pub temperature: String,
+    pub top_k: String,
+    pub top_p: String,
+    pub max_tokens: String,
+    pub stop: String,
+    pub seed: String,
+}
+
+impl Default for InfiniLMKeys {
+    fn default() -> Self {
+        Self {
+            inputs: "inputs".to_string(),
+            session_id: "session_id".to_string(),
+            new_session_id: "new_session_id".to_string(),
+            dialog_pos: "dialog_pos".to_string(),
+            temperature: "temperature".to_string(),
+            top_k: "top-k".to_string(),
+            top_p: "top-p".to_string(),
+            max_tokens: "max_tokens".to_string(),
+            stop: "stop".to_string(),
+            seed: "seed".to_string(),
+        }
+    }
+}
+
+impl InfiniLMConfig {
+    fn profile(&self) -> Profile {
+        Profile {
+            generation: self.generation.clone(),
+            infinilm: self.options.clone(),
+            network: self.network.clone(),
+            ..Profile::legacy(LLMBackend::InfiniLM, &self.url, self.model.as_deref())
+        }
+    }
+
+    pub fn default_url() -> String {
+        String::from("http://localhost:3000/infer")
+    }
+}
+
+// llama.cpp
+#[derive(Deserialize, Debug, Clone)]
+pub struct LlamaCppConfig {
+    /// Address of the llama.cpp server, without the endpoint path.
+    #[serde(default = "LlamaCppConfig::default_url")]
+    pub url: String,
+
+    /// Only used to label the backend, the server decides which GGUF model is loaded.
+    pub model: Option<String>,
+
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(flatten)]
+    pub options: LlamaCppOptions,
+
+    #[serde(flatten)]
+    pub generation: GenerationConfig,
+}
+
+impl LlamaCppConfig {
+    fn profile(&self) -> Profile {
+        Profile {
+            generation: self.generation.clone(),
+            llamacpp: self.options.clone(),
+            network: self.network.clone(),
+            ..Profile::legacy(LLMBackend::LlamaCpp, &self.url, self.model.as_deref())
+        }
+    }
+
+    pub fn default_url() -> String {
+        String::from("http://localhost:8080")
+    }
+}
+
+/// Which llama.cpp streaming endpoint to use.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LlamaCppEndpoint {
+    /// `/v1/chat/completions`, the server applies the model's chat template.
+    #[default]
+    Chat,
+    /// `/completion`, with the conversation rendered as a plain-text prompt.
+    Completion,
+}
+
+/// llama.cpp specific request parameters, ignored by the other backends.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LlamaCppOptions {
+    #[serde(default)]
+    pub endpoint: LlamaCppEndpoint,
+    pub n_predict: Option<i32>,
+    pub cache_prompt: Option<bool>,
+    /// GBNF grammar constraining the output.
+    pub grammar: Option<String>,
+    /// JSON schema constraining the output, as a TOML table.
+    pub json_schema: Option<serde_json::Value>,
+    pub slot_id: Option<i32>,
+}
+
+// Mock
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MockConfig {
+    #[serde(flatten)]
+    pub options: MockOptions,
+}
+
+impl MockConfig {
+    fn profile(&self) -> Profile {
+        Profile {
+            url: None,
+            mock: self.options.clone(),
+            ..Profile::legacy(LLMBackend::Mock, "", Some("mock"))
+        }
+    }
+}
+
+/// What the offline mock backend answers.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MockMode {
+    /// Repeats the last user message.
+    #[default]
+    Echo,
+    /// Canned markdown with headings, lists and code blocks.
+    Markdown,
+    /// The contents of the `fixture` file.
+    Fixture,
+}
+
+/// Settings of the mock backend, ignored by the other backends.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MockOptions {
+    #[serde(default)]
+    pub mode: MockMode,
+    pub fixture: Option<PathBuf>,
+    /// Characters per streamed chunk.
+    #[serde(default = "MockOptions::default_chunk_size")]
+    pub chunk_size: usize,
+    /// Pause between chunks, in milliseconds.
+    #[serde(default = "MockOptions::default_delay_ms")]
+    pub delay_ms: u64,
+    /// Fails the answer after this many chunks.
+    pub fail_after: Option<usize>,
+}
+
+impl Default for MockOptions {
+    fn default() -> Self {
+        Self {
+            mode: MockMode::default(),
+            fixture: None,
+            chunk_size: Self::default_chunk_size(),
+            delay_ms: Self::default_delay_ms(),
+            fail_after: None,
+        }
+    }
+}
+
+impl MockOptions {
+    fn default_chunk_size() -> usize {
+        4
+    }
+
+    fn default_delay_ms() -> u64 {
+        30
+    }
 }
 
 // Key Bindings
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, PartialEq)]
 pub struct KeyBindings {
     #[serde(default = "KeyBindings::default_show_help")]
     pub show_help: char,
@@ -157,6 +712,21 @@ pub struct KeyBindings {
 
     #[serde(default = "KeyBindings::default_code_to_prompt")]
     pub code_to_prompt: char,
+
+    #[serde(default = "KeyBindings::default_switch_backend")]
+    pub switch_backend: char,
+
+    #[serde(default = "KeyBindings::default_show_queue")]
+    pub show_queue: char,
+
+    #[serde(default = "KeyBindings::default_attach_image")]
+    pub attach_image: char,
+
+    #[serde(default = "KeyBindings::default_show_models")]
+    pub show_models: char,
+
+    #[serde(default = "KeyBindings::default_regenerate")]
+    pub regenerate: char,
 }
 
 impl Default for KeyBindings {
@@ -169,6 +739,11 @@ impl Default for KeyBindings {
             stop_stream: 't',
             show_file_explorer: 'f',
             code_to_prompt: 'p',
+            switch_backend: 'b',
+            show_queue: 'u',
+            attach_image: 'a',
+            show_models: 'o',
+            regenerate: 'r',
         }
     }
 }
@@ -201,40 +776,282 @@ impl KeyBindings {
     fn default_code_to_prompt() -> char {
         'p'
     }
+
+    fn default_switch_backend() -> char {
+        'b'
+    }
+
+    fn default_show_queue() -> char {
+        'u'
+    }
+
+    fn default_attach_image() -> char {
+        'a'
+    }
+
+    fn default_show_models() -> char {
+        'o'
+    }
+
+    fn default_regenerate() -> char {
+        'r'
+    }
+}
+
+/// `CONFIG_DIR`, or `infini` in the platform's config directory.
+pub fn config_dir() -> PathBuf {
+    match env::var("CONFIG_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(env::VarError::NotPresent) => dirs::config_dir().unwrap().join("infini"),
+        Err(e) => {
+            panic!("Failed to read CONFIG_DIR: {}", e);
+        }
+    }
 }
 
 impl Config {
     pub fn load() -> Self {
-        let conf_dir = match env::var("CONFIG_DIR") {
-            Ok(dir) => {
-                info!("Using custom config directory: {}", dir);
-                PathBuf::from(dir)
-            }
-            Err(env::VarError::NotPresent) => {
-                let default_dir = dirs::config_dir().unwrap().join("infini");
-                info!("Using default config directory: {:?}", default_dir);
-                default_dir
-            }
-            Err(e) => {
-                panic!("Failed to read CONFIG_DIR: {}", e);
-            }
-        };
+        let conf_dir = config_dir();
+        info!("Using config directory: {:?}", conf_dir);
 
         // 构造完整的配置文件路径
         let conf_path = conf_dir.join("config.toml");
 
-        let config = std::fs::read_to_string(&conf_path)
-            .unwrap_or_else(|_| panic!("Failed to read config file: {:?}", conf_path));
-        let mut app_config: Config = toml::from_str(&config)
-            .unwrap_or_else(|_| panic!("Failed to parse config file: {:?}", conf_path));
-
-        if app_config.llm == LLMBackend::Ollama && app_config.ollama.is_none() {
-            eprintln!("Config for Ollama is not provided");
+        let app_config = Config::read(&conf_path).unwrap_or_else(|e| {
+            eprintln!("Failed to load config file: {}", e);
             std::process::exit(1);
-        }
+        });
 
         eprintln!("app_config.language {:?}", app_config.language);
 
         app_config
     }
+
+    /// Reads and parses the config file at `path`. Syntax errors name their
+    /// line and column.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        Config::parse(&contents).map_err(|e| match e.downcast_ref::<toml::de::Error>() {
+            Some(e) => toml_error(path, &contents, e),
+            None => format!("{:?}: {}", path, e),
+        })
+    }
+
+    /// Parses `config.toml`, turning the per-backend sections into profiles.
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config: Config = toml::from_str(contents)?;
+
+        // 旧版配置：每种后端一个配置段，转换为同名 profile
+        let selected = config.llm;
+        let mut legacy = Vec::new();
+        if config.chatgpt.openai_api_key.is_some()
+            || env::var("OPENAI_API_KEY").is_ok()
+            || selected == Some(LLMBackend::ChatGPT)
+        {
+            legacy.push(config.chatgpt.profile());
+        }
+        if config.chatglm.chatglm_api_key.is_some()
+            || env::var("CHATML_API_KEY").is_ok()
+            || selected == Some(LLMBackend::ChatGLM)
+            || (selected.is_none() && config.profiles.is_empty())
+        {
+            legacy.push(config.chatglm.profile());
+        }
+        if config.anthropic.anthropic_api_key.is_some()
+            || env::var("ANTHROPIC_API_KEY").is_ok()
+            || selected == Some(LLMBackend::Anthropic)
+        {
+            legacy.push(config.anthropic.profile());
+        }
+        legacy.extend(config.llamacpp.as_ref().map(LlamaCppConfig::profile));
+        legacy.extend(config.ollama.as_ref().map(OllamaConfig::profile));
+        legacy.extend(config.infinilm.as_ref().map(InfiniLMConfig::profile));
+        if config.mock.is_some() || selected == Some(LLMBackend::Mock) {
+            legacy.push(config.mock.clone().unwrap_or_default().profile());
+        }
+
+        for profile in legacy {
+            if !config.profiles.iter().any(|p| p.name == profile.name) {
+                config.profiles.push(profile);
+            }
+        }
+
+        let default_profile = config
+            .default_profile
+            .clone()
+            .or_else(|| selected.map(|backend| backend.to_string()))
+            .or_else(|| config.profiles.first().map(|p| p.name.clone()))
+            .unwrap_or_else(|| default_llm_backend().to_string());
+
+        if config.profile(&default_profile).is_none() {
+            return Err(format!("Config for profile `{}` is not provided", default_profile).into());
+        }
+        config.default_profile = Some(default_profile);
+
+        Ok(config)
+    }
+
+    /// The settings that differ in `next`, to tell what a reload changed.
+    pub fn changes(&self, next: &Config) -> Vec<&'static str> {
+        [
+            ("key bindings", self.key_bindings != next.key_bindings),
+            ("profiles", self.profiles != next.profiles),
+            (
+                "default profile",
+                self.default_profile != next.default_profile,
+            ),
+            ("default preset", self.default_preset != next.default_preset),
+            ("generation", self.generation != next.generation),
+            ("context", self.context != next.context),
+            ("language", self.language != next.language),
+            (
+                "file explorer path",
+                self.file_explorer_path != next.file_explorer_path,
+            ),
+            (
+                "archive file name",
+                self.archive_file_name != next.archive_file_name,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(name, changed)| changed.then_some(name))
+        .collect()
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// The profile used at startup, always present after [`Config::parse`].
+    pub fn default_profile(&self) -> &Profile {
+        self.default_profile
+            .as_deref()
+            .and_then(|name| self.profile(name))
+            .expect("default profile is resolved when the config is parsed")
+    }
+}
+
+/// `path:line:column: message` of a TOML error in `contents`.
+pub fn toml_error(path: &Path, contents: &str, e: &toml::de::Error) -> String {
+    let Some(span) = e.span() else {
+        return format!("{}: {}", path.display(), e.message());
+    };
+    let before = &contents[..span.start.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    format!("{}:{}:{}: {}", path.display(), line, column, e.message())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::TempDir;
+
+    #[test]
+    fn backend_generation_overrides_shared_settings() {
+        let config: Config = toml::from_str(
+            r#"
+            llm = "ollama"
+
+            [generation]
+            temperature = 0.2
+            seed = 42
+            stop = ["</s>"]
+
+            [ollama]
+            url = "http://localhost:11434/api/chat"
+            model = "llama2"
+            temperature = 0.6
+            top_k = 40
+            "#,
+        )
+        .unwrap();
+
+        let ollama = config.ollama.unwrap();
+        assert_eq!(ollama.model.as_deref(), Some("llama2"));
+        assert_eq!(
+            ollama.generation.or(&config.generation),
+            GenerationConfig {
+                temperature: Some(0.6),
+                top_k: Some(40),
+                seed: Some(42),
+                stop: Some(vec!["</s>".to_string()]),
+                ..Default::default()
+            }
+        );
+        assert_eq!(config.chatgpt.generation, GenerationConfig::default());
+    }
+
+    #[test]
+    fn profiles_and_legacy_sections_load_together() {
+        let config = Config::parse(
+            r#"
+            default_profile = "infinilm-q8"
+
+            [[profiles]]
+            name = "infinilm-fp16"
+            backend = "infinilm"
+            url = "http://localhost:3000/infer"
+
+            [[profiles]]
+            name = "infinilm-q8"
+            backend = "infinilm"
+            url = "http://localhost:3001/infer"
+            temperature = 0.1
+
+            [ollama]
+            url = "http://localhost:11434/api/chat"
+            model = "llama2"
+            "#,
+        )
+        .unwrap();
+
+        let default = config.default_profile();
+        assert_eq!(default.url.as_deref(), Some("http://localhost:3001/infer"));
+        assert_eq!(default.generation.temperature, Some(0.1));
+        assert_eq!(
+            config.profile("ollama").unwrap().model.as_deref(),
+            Some("llama2")
+        );
+
+        let legacy = Config::parse(
+            r#"
+            llm = "infinilm"
+
+            [infinilm]
+            url = "http://localhost:3000/infer"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(legacy.default_profile().backend, LLMBackend::InfiniLM);
+
+        assert!(Config::parse(r#"default_profile = "missing""#).is_err());
+    }
+
+    #[test]
+    fn reloads_report_changes_and_error_positions() {
+        let dir = TempDir::new();
+        let path = dir.join("config.toml");
+        fs::write(&path, "llm = \"mock\"\nlanguage = \"en\"\n").unwrap();
+        let config = Config::read(&path).unwrap();
+
+        fs::write(
+            &path,
+            "llm = \"mock\"\nlanguage = \"zh-CN\"\n[key_bindings]\nnew_chat = 'm'\n",
+        )
+        .unwrap();
+        let next = Config::read(&path).unwrap();
+        assert_eq!(config.changes(&next), vec!["key bindings", "language"]);
+        assert!(next.changes(&next).is_empty());
+
+        fs::write(&path, "llm = \"mock\"\n[key_bindings]\nnew_chat = m\n").unwrap();
+        let error = Config::read(&path).unwrap_err();
+        assert!(
+            error.starts_with(&format!("{}:3:12: ", path.display())),
+            "{}",
+            error
+        );
+    }
 }
@@ -1,27 +1,36 @@
-use crate::llm::{LLMAnswer, LLMRole};
-use crate::ui::{prompt::Mode, Chat, Notification, NotificationLevel};
+use crate::config::{config_dir, Config, Profile};
+use crate::llm::ollama::{ModelEvent, Ollama, OllamaApi};
+use crate::llm::preset;
+use crate::llm::usage::{estimate_prompt, estimate_tokens};
+use crate::llm::LLM;
+use crate::llm::{
+    ask_until_cancelled, Attachment, LLMAnswer, LLMBackend, LLMError, LLMModel, LLMRole, Message,
+};
+use crate::ui::file_explore::FileExplorer;
+use crate::ui::{
+    chat::transcript, prompt::Mode, BackendPicker, Chat, Notification, NotificationLevel,
+};
 use crate::utils::code2prompt;
 use crate::{
     app::{App, AppResult, FocusedBlock},
     event::Event,
 };
-use tracing::info;
-
-use crate::llm::LLM;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
+use ratatui::style::{Color, Style};
 use ratatui::text::Line;
 use tracing::error;
 
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_util::sync::CancellationToken;
 
 pub async fn handle_key_events(
     key_event: KeyEvent,
     app: &mut App<'_>,
-    llm: Arc<Mutex<Box<dyn LLM + 'static>>>,
+    llm: &mut Arc<dyn LLM>,
     sender: UnboundedSender<Event>,
 ) -> AppResult<()> {
     match key_event.code {
@@ -34,10 +43,24 @@ pub async fn handle_key_events(
             app.running = false;
         }
 
+        // Allow or deny the tool call the model asked for
+        KeyCode::Char(c @ ('y' | 'n'))
+            if app.pending_tool.is_some()
+                && app.prompt.mode != Mode::Insert
+                && key_event.modifiers == KeyModifiers::NONE =>
+        {
+            if let Some(request) = app.pending_tool.take() {
+                let allowed = c == 'y';
+                app.chat.tool_answered(&request.call, allowed);
+                request.answer(allowed);
+            }
+            app.previous_key = key_event.code;
+            return Ok(());
+        }
+
         // Terminate the stream response
         KeyCode::Char('t') if key_event.modifiers == KeyModifiers::CONTROL => {
-            app.terminate_response_signal
-                .store(true, std::sync::atomic::Ordering::Relaxed);
+            app.cancel_request.cancel();
         }
 
         // scroll down
@@ -70,6 +93,11 @@ pub async fn handle_key_events(
                 app.file_explorer.preview.scroll =
                     app.file_explorer.preview.scroll.saturating_add(1);
             }
+
+            FocusedBlock::BackendPicker => app.backend_picker.scroll_down(),
+            FocusedBlock::ModelPicker => app.model_picker.scroll_down(),
+            FocusedBlock::PresetPicker => app.preset_picker.scroll_down(),
+            FocusedBlock::Queue => app.queue.scroll_down(),
             _ => (),
         },
 
@@ -103,6 +131,11 @@ pub async fn handle_key_events(
                     app.file_explorer.preview.scroll.saturating_sub(1);
             }
 
+            FocusedBlock::BackendPicker => app.backend_picker.scroll_up(),
+            FocusedBlock::ModelPicker => app.model_picker.scroll_up(),
+            FocusedBlock::PresetPicker => app.preset_picker.scroll_up(),
+            FocusedBlock::Queue => app.queue.scroll_up(),
+
             _ => (),
         },
 
@@ -114,19 +147,15 @@ pub async fn handle_key_events(
         },
 
         // `gg`: Move to the top
-        KeyCode::Char('g') => {
-            if app.previous_key == KeyCode::Char('g') {
-                match app.focused_block {
-                    FocusedBlock::Chat => {
-                        app.chat.move_to_top();
-                    }
-                    FocusedBlock::History => {
-                        app.history.move_to_top();
-                    }
-                    _ => (),
-                }
+        KeyCode::Char('g') if app.previous_key == KeyCode::Char('g') => match app.focused_block {
+            FocusedBlock::Chat => {
+                app.chat.move_to_top();
             }
-        }
+            FocusedBlock::History => {
+                app.history.move_to_top();
+            }
+            _ => (),
+        },
 
         // New chat
         KeyCode::Char(c)
@@ -135,22 +164,32 @@ pub async fn handle_key_events(
         {
             app.prompt.clear();
 
+            // The answer being streamed belongs to the previous chat
+            app.cancel_request.cancel();
+            app.answer_id += 1;
+            app.answering = false;
+            app.spinner.active = false;
+            app.pending_tool = None;
+
             app.history
                 .preview
                 .text
                 .push(app.chat.formatted_chat.clone());
 
-            app.history.text.push(app.chat.plain_chat.clone());
+            app.history.text.push(app.chat.messages.clone());
+            app.history.presets.push(app.chat.preset.clone());
 
-            app.chat = Chat::default();
-
-            let llm = llm.clone();
-            {
-                let mut llm = llm.lock().await;
-                llm.clear();
-            }
+            app.chat = Chat::new(&app.chat.preset);
+            app.queue.clear();
+            app.attachments.clear();
 
             app.chat.scroll = 0;
+
+            // Let the new conversation pick its prompts, when there is a choice
+            let presets = preset::names(&config_dir());
+            if presets.len() > 1 {
+                open_preset_picker(app, presets);
+            }
         }
 
         // Save chat
@@ -166,7 +205,7 @@ pub async fn handle_key_events(
                 FocusedBlock::Chat | FocusedBlock::Prompt => {
                     match std::fs::write(
                         app.config.archive_file_name.clone(),
-                        app.chat.plain_chat.join(""),
+                        transcript(&app.chat.messages),
                     ) {
                         Ok(_) => {
                             let notif = Notification::new(
@@ -214,6 +253,19 @@ pub async fn handle_key_events(
             }
         }
 
+        // Attach the selected image to the next prompt
+        KeyCode::Char(c)
+            if c == app.config.key_bindings.attach_image
+                && key_event.modifiers == KeyModifiers::CONTROL =>
+        {
+            if let FocusedBlock::FileExplorer | FocusedBlock::FileExplorerPreview =
+                app.focused_block
+            {
+                let path = app.file_explorer.current().path().clone();
+                attach_image(app, &path, &sender);
+            }
+        }
+
         // Switch the focus
         KeyCode::Tab => match app.focused_block {
             FocusedBlock::Chat => {
@@ -297,13 +349,132 @@ pub async fn handle_key_events(
                 .store(true, std::sync::atomic::Ordering::Relaxed);
         }
 
+        // Show backend picker
+        KeyCode::Char(c)
+            if c == app.config.key_bindings.switch_backend
+                && app.prompt.mode != Mode::Insert
+                && key_event.modifiers == KeyModifiers::CONTROL =>
+        {
+            app.backend_picker.open(&app.llm_profile);
+            app.focused_block = FocusedBlock::BackendPicker;
+            app.prompt.update(&app.focused_block);
+        }
+
+        // Switch to the selected backend
+        KeyCode::Enter if app.focused_block == FocusedBlock::BackendPicker => {
+            app.focused_block = FocusedBlock::Prompt;
+            app.prompt.update(&app.focused_block);
+            if let Some(profile) = app.backend_picker.selected() {
+                switch_backend(app, llm, &profile, &sender).await;
+            }
+            app.previous_key = key_event.code;
+            return Ok(());
+        }
+
+        // Show the models of the Ollama server
+        KeyCode::Char(c)
+            if c == app.config.key_bindings.show_models
+                && app.prompt.mode != Mode::Insert
+                && key_event.modifiers == KeyModifiers::CONTROL =>
+        {
+            open_model_picker(app, &sender);
+        }
+
+        // Switch to the highlighted model
+        KeyCode::Enter if app.focused_block == FocusedBlock::ModelPicker => {
+            app.focused_block = FocusedBlock::Prompt;
+            app.prompt.update(&app.focused_block);
+            if let Some(model) = app.model_picker.selected().map(|m| m.name.clone()) {
+                switch_model(app, llm, &model, &sender);
+            }
+            app.previous_key = key_event.code;
+            return Ok(());
+        }
+
+        // Name the model to pull in the prompt
+        KeyCode::Char('p') if app.focused_block == FocusedBlock::ModelPicker => {
+            app.prompt.set_text("/pull ");
+            app.prompt.mode = Mode::Insert;
+            app.focused_block = FocusedBlock::Prompt;
+            app.prompt.update(&app.focused_block);
+            app.previous_key = key_event.code;
+            return Ok(());
+        }
+
+        KeyCode::Char('u') if app.focused_block == FocusedBlock::ModelPicker => {
+            if let Some(model) = app.model_picker.selected().map(|m| m.name.clone()) {
+                unload_model(app, &model, &sender);
+            }
+        }
+
+        // Use the highlighted preset for the conversation
+        KeyCode::Enter if app.focused_block == FocusedBlock::PresetPicker => {
+            app.focused_block = FocusedBlock::Prompt;
+            app.prompt.update(&app.focused_block);
+            if let Some(name) = app.preset_picker.selected() {
+                app.chat.preset = name;
+            }
+            app.previous_key = key_event.code;
+            return Ok(());
+        }
+
+        // Ask again for the last answer
+        KeyCode::Char(c)
+            if c == app.config.key_bindings.regenerate
+                && app.prompt.mode != Mode::Insert
+                && key_event.modifiers == KeyModifiers::CONTROL =>
+        {
+            regenerate(app, llm, &sender);
+        }
+
+        // Show the other answers to the last prompt
+        KeyCode::Char(c @ ('<' | '>'))
+            if matches!(app.focused_block, FocusedBlock::Prompt | FocusedBlock::Chat)
+                && app.prompt.mode != Mode::Insert
+                && !app.answering =>
+        {
+            app.chat.cycle_sibling(c == '>', app.formatter);
+        }
+
+        // Show the prompt queue
+        KeyCode::Char(c)
+            if c == app.config.key_bindings.show_queue
+                && app.prompt.mode != Mode::Insert
+                && key_event.modifiers == KeyModifiers::CONTROL =>
+        {
+            app.queue.open();
+            app.focused_block = FocusedBlock::Queue;
+            app.prompt.update(&app.focused_block);
+        }
+
+        // Take a queued prompt back into the prompt to edit it
+        KeyCode::Char('e') | KeyCode::Enter if app.focused_block == FocusedBlock::Queue => {
+            if let Some(mut message) = app.queue.edit() {
+                app.attachments = std::mem::take(&mut message.attachments);
+                let text = if message.pinned {
+                    format!("/pin {}", message.content)
+                } else {
+                    message.content
+                };
+                app.prompt.set_text(&text);
+                app.focused_block = FocusedBlock::Prompt;
+                app.prompt.update(&app.focused_block);
+            }
+            app.previous_key = key_event.code;
+            return Ok(());
+        }
+
+        KeyCode::Char('d') if app.focused_block == FocusedBlock::Queue => {
+            app.queue.remove();
+        }
+
         // control File explore
         // j k in explorer handle in above snippet
-        KeyCode::Char('h') | KeyCode::Left | KeyCode::Right => {
-            if app.focused_block == FocusedBlock::FileExplorer {
-                if let Err(e) = app.file_explorer.handle(&key_event) {
-                    error!("Error handling file explorer: {}", e);
-                }
+        KeyCode::Char('h') | KeyCode::Left | KeyCode::Right
+            if app.focused_block == FocusedBlock::FileExplorer =>
+        {
+            if let Err(e) = app.file_explorer.handle(&key_event) {
+                error!("Error handling file explorer: {}", e);
             }
         }
 
@@ -313,7 +484,11 @@ pub async fn handle_key_events(
             | FocusedBlock::Preview
             | FocusedBlock::Help
             | FocusedBlock::FileExplorer
-            | FocusedBlock::FileExplorerPreview => app.focused_block = FocusedBlock::Prompt,
+            | FocusedBlock::FileExplorerPreview
+            | FocusedBlock::BackendPicker
+            | FocusedBlock::ModelPicker
+            | FocusedBlock::PresetPicker
+            | FocusedBlock::Queue => app.focused_block = FocusedBlock::Prompt,
             _ => {}
         },
 
@@ -331,60 +506,451 @@ pub async fn handle_key_events(
 
                 app.prompt.clear();
 
-                app.chat.plain_chat.push(format!("👤 : {}\n", user_input));
-
-                if app.chat.formatted_chat.width() == 0 {
-                    app.chat.formatted_chat = app
-                        .formatter
-                        .format(format!("👤: {}\n", user_input).as_str());
-                } else {
-                    app.chat.formatted_chat.extend(
-                        app.formatter
-                            .format(format!("👤: {}\n", user_input).as_str()),
-                    );
+                if let Some(name) = command_argument(user_input, "/backend") {
+                    if name.is_empty() {
+                        app.backend_picker.open(&app.llm_profile);
+                        app.focused_block = FocusedBlock::BackendPicker;
+                        app.prompt.update(&app.focused_block);
+                    } else {
+                        switch_backend(app, llm, name, &sender).await;
+                    }
+                    app.previous_key = key_event.code;
+                    return Ok(());
                 }
 
-                let llm = llm.clone();
-                {
-                    let mut llm = llm.lock().await;
-                    llm.append_chat_msg(user_input.into(), LLMRole::USER);
+                if command_argument(user_input, "/models").is_some() {
+                    open_model_picker(app, &sender);
+                    app.previous_key = key_event.code;
+                    return Ok(());
                 }
 
-                app.spinner.active = true;
-
-                app.chat
-                    .formatted_chat
-                    .lines
-                    .push(Line::raw("🤖: ".to_string()));
-
+                if let Some(model) = command_argument(user_input, "/pull") {
+                    pull_model(app, model, &sender);
+                    app.previous_key = key_event.code;
+                    return Ok(());
+                }
 
-                let terminate_response_signal = app.terminate_response_signal.clone();
+                if command_argument(user_input, "/preset").is_some() {
+                    open_preset_picker(app, preset::names(&config_dir()));
+                    app.previous_key = key_event.code;
+                    return Ok(());
+                }
 
-                let sender = sender.clone();
+                if command_argument(user_input, "/regenerate").is_some() {
+                    regenerate(app, llm, &sender);
+                    app.previous_key = key_event.code;
+                    return Ok(());
+                }
 
-                let llm = llm.clone();
+                if command_argument(user_input, "/queue").is_some() {
+                    app.queue.open();
+                    app.focused_block = FocusedBlock::Queue;
+                    app.prompt.update(&app.focused_block);
+                    app.previous_key = key_event.code;
+                    return Ok(());
+                }
 
-                tokio::spawn(async move {
-                    let llm = llm.lock().await;
-                    let res = llm.ask(sender.clone(), terminate_response_signal).await;
+                if let Some(path) = command_argument(user_input, "/image") {
+                    attach_image(app, Path::new(path), &sender);
+                    app.previous_key = key_event.code;
+                    return Ok(());
+                }
 
-                    if let Err(e) = res {
-                        sender
-                            .send(Event::LLMEvent(LLMAnswer::StartAnswer))
-                            .unwrap();
-                        sender
-                            .send(Event::LLMEvent(LLMAnswer::Answer(e.to_string())))
-                            .unwrap();
+                // `/pin <message>` sends a message that is never left out of a request
+                let (user_input, pinned) = match command_argument(user_input, "/pin") {
+                    Some("") => {
+                        pin_last_message(app, &sender);
+                        app.previous_key = key_event.code;
+                        return Ok(());
                     }
-                });
+                    Some(text) => (text, true),
+                    None => (user_input, false),
+                };
+
+                let mut message = Message::new(LLMRole::USER, user_input.into());
+                message.tokens = Some(estimate_tokens(&message.content));
+                message.pinned = pinned;
+                message.attachments = std::mem::take(&mut app.attachments);
+
+                // One answer at a time, prompts sent meanwhile wait their turn. An
+                // edited prompt goes back at its place.
+                app.queue.reinsert(message);
+                send_queued(app, llm, &sender);
             }
         }
 
         app.prompt
             .handler(key_event, app.previous_key, app.clipboard.as_mut());
+
+        // Emptying the prompt gives up the edit of a queued prompt
+        if app.prompt.editor.lines().iter().all(|line| line.is_empty()) {
+            app.queue.abandon_edit();
+        }
     }
 
     app.previous_key = key_event.code;
 
     Ok(())
 }
+
+/// Sends the next queued prompt, unless an answer is still streaming.
+pub fn send_queued(app: &mut App<'_>, llm: &Arc<dyn LLM>, sender: &UnboundedSender<Event>) {
+    if app.answering {
+        return;
+    }
+    let Some(message) = app.queue.pop() else {
+        return;
+    };
+
+    let prompt = format!("👤: {}\n", message.content);
+
+    if app.chat.formatted_chat.width() == 0 {
+        app.chat.formatted_chat = app.formatter.format(&prompt);
+    } else {
+        app.chat
+            .formatted_chat
+            .extend(app.formatter.format(&prompt));
+    }
+    for image in &message.attachments {
+        app.chat.formatted_chat.lines.push(Line::styled(
+            image.label(),
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+    app.chat.push_prompt(message);
+    ask(app, llm, sender);
+}
+
+/// Asks the backend to answer the conversation as it is.
+fn ask(app: &mut App<'_>, llm: &Arc<dyn LLM>, sender: &UnboundedSender<Event>) {
+    app.spinner.active = true;
+    app.answering = true;
+    app.chat.await_answer();
+
+    // A fresh token, so stopping this answer leaves the next one alone
+    app.cancel_request = CancellationToken::new();
+    let cancel = app.cancel_request.clone();
+
+    app.answer_id += 1;
+    let answer_id = app.answer_id;
+
+    let sender = sender.clone();
+
+    let llm = llm.clone();
+
+    // The request works on a snapshot, the chat stays free to change meanwhile
+    let variables = preset::variables(&app.llm_model, &app.config.file_explorer_path);
+    let messages = match preset::request_messages(&app.chat.preset, &app.chat.messages, &variables)
+    {
+        Ok(messages) => messages,
+        Err(e) => {
+            let _ = sender.send(Event::Answer(
+                answer_id,
+                LLMAnswer::Error(LLMError::Config(e)),
+            ));
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        // The backend's events are tagged with the answer they belong to
+        let (answers, mut received) = unbounded_channel();
+        let request = async move {
+            let estimate = LLMAnswer::PromptEstimate(estimate_prompt(&messages));
+            let _ = answers.send(Event::LLMEvent(estimate));
+            let res = ask_until_cancelled(llm.as_ref(), &messages, answers.clone(), cancel).await;
+            if let Err(e) = res {
+                let _ = answers.send(Event::LLMEvent(LLMAnswer::Error(e)));
+            }
+        };
+        let forward = async {
+            while let Some(event) = received.recv().await {
+                let event = match event {
+                    Event::LLMEvent(answer) => Event::Answer(answer_id, answer),
+                    event => event,
+                };
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::join!(request, forward);
+    });
+}
+
+fn open_preset_picker(app: &mut App<'_>, presets: Vec<String>) {
+    app.preset_picker.open(presets, &app.chat.preset);
+    app.focused_block = FocusedBlock::PresetPicker;
+    app.prompt.update(&app.focused_block);
+}
+
+/// Returns the trimmed argument when `input` is the slash command `command`.
+fn command_argument<'a>(input: &'a str, command: &str) -> Option<&'a str> {
+    let rest = input.strip_prefix(command)?;
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+/// Asks again for the last answer, the previous ones stay as its siblings.
+fn regenerate(app: &mut App<'_>, llm: &Arc<dyn LLM>, sender: &UnboundedSender<Event>) {
+    if app.answering {
+        let notif = Notification::new(
+            "Wait until the current answer is finished".to_string(),
+            NotificationLevel::Warning,
+        );
+        sender.send(Event::Notification(notif)).unwrap();
+        return;
+    }
+    if !app.chat.regenerate() {
+        let notif = Notification::new(
+            "There is no answer to regenerate".to_string(),
+            NotificationLevel::Warning,
+        );
+        sender.send(Event::Notification(notif)).unwrap();
+        return;
+    }
+    ask(app, llm, sender);
+}
+
+/// Replaces the active backend with the one of `profile`. The conversation
+/// lives in the chat, so it carries over; an answer being streamed finishes
+/// on the previous backend.
+async fn switch_backend(
+    app: &mut App<'_>,
+    llm: &mut Arc<dyn LLM>,
+    profile: &str,
+    sender: &UnboundedSender<Event>,
+) {
+    let config = app.config.clone();
+    let Some(profile) = config.profile(profile) else {
+        let notif = Notification::new(
+            format!("Unknown profile `{}`", profile),
+            NotificationLevel::Error,
+        );
+        sender.send(Event::Notification(notif)).unwrap();
+        return;
+    };
+
+    let notif = match LLMModel::init(profile, &config).await {
+        Ok(next) => {
+            *llm = Arc::from(next);
+            app.set_llm(&profile.name, llm.as_ref());
+            Notification::new(
+                format!("Switched to {}", app.llm_label()),
+                NotificationLevel::Info,
+            )
+        }
+        Err(e) => Notification::new(e.to_string(), NotificationLevel::Error),
+    };
+
+    sender.send(Event::Notification(notif)).unwrap();
+}
+
+/// Applies a reloaded `config.toml` to the running app. The active profile
+/// is connected again when its settings changed, or replaced by the default
+/// one when it was removed.
+pub async fn apply_config(
+    app: &mut App<'_>,
+    llm: &mut Arc<dyn LLM>,
+    config: Arc<Config>,
+    sender: &UnboundedSender<Event>,
+) {
+    let changes = app.config.changes(&config);
+    if changes.is_empty() {
+        return;
+    }
+    let previous = std::mem::replace(&mut app.config, config.clone());
+
+    if previous.language != config.language {
+        crate::set_language(&config.language);
+    }
+    if previous.file_explorer_path != config.file_explorer_path {
+        app.file_explorer = FileExplorer::new(&config.file_explorer_path);
+    }
+    app.backend_picker = BackendPicker::new(config.profiles.clone());
+
+    let notif = Notification::new(
+        format!("Config reloaded: {} changed", changes.join(", ")),
+        NotificationLevel::Info,
+    );
+    sender.send(Event::Notification(notif)).unwrap();
+
+    let active = app.llm_profile.clone();
+    match config.profile(&active) {
+        Some(profile)
+            if previous.profile(&active) != Some(profile)
+                || previous.generation != config.generation
+                || previous.context != config.context =>
+        {
+            switch_backend(app, llm, &active, sender).await;
+        }
+        Some(_) => {}
+        None => {
+            let default = config.default_profile().name.clone();
+            switch_backend(app, llm, &default, sender).await;
+        }
+    }
+}
+
+/// Reads the image at `path`, it is sent along with the next prompt.
+fn attach_image(app: &mut App<'_>, path: &Path, sender: &UnboundedSender<Event>) {
+    let notif = match Attachment::image(path) {
+        Ok(image) => {
+            let notif = Notification::new(
+                format!(
+                    "Attached {}, it is sent with the next prompt",
+                    image.label()
+                ),
+                NotificationLevel::Info,
+            );
+            app.attachments.push(image);
+            notif
+        }
+        Err(e) => Notification::new(e, NotificationLevel::Error),
+    };
+
+    sender.send(Event::Notification(notif)).unwrap();
+}
+
+/// The model management api of the active profile, when it is an Ollama one.
+fn ollama_api(app: &App<'_>, sender: &UnboundedSender<Event>) -> Option<OllamaApi> {
+    let api = match app.config.profile(&app.llm_profile) {
+        Some(profile) if profile.backend == LLMBackend::Ollama => {
+            OllamaApi::new(profile).map_err(|e| e.to_string())
+        }
+        _ => Err(format!(
+            "The profile `{}` is not an Ollama one",
+            app.llm_profile
+        )),
+    };
+    match api {
+        Ok(api) => Some(api),
+        Err(e) => {
+            let notif = Notification::new(e, NotificationLevel::Error);
+            sender.send(Event::Notification(notif)).unwrap();
+            None
+        }
+    }
+}
+
+fn open_model_picker(app: &mut App<'_>, sender: &UnboundedSender<Event>) {
+    let Some(api) = ollama_api(app, sender) else {
+        return;
+    };
+    app.model_picker.open();
+    app.focused_block = FocusedBlock::ModelPicker;
+    app.prompt.update(&app.focused_block);
+
+    let sender = sender.clone();
+    tokio::spawn(async move { list_models(&api, &sender).await });
+}
+
+/// Refreshes the model picker, an unreachable server leaves it empty.
+async fn list_models(api: &OllamaApi, sender: &UnboundedSender<Event>) {
+    let models = api.models(sender).await.unwrap_or_else(|e| {
+        let notif = Notification::new(e.to_string(), NotificationLevel::Error);
+        let _ = sender.send(Event::Notification(notif));
+        Vec::new()
+    });
+    let _ = sender.send(Event::Models(ModelEvent::Listed(models)));
+}
+
+/// Downloads `model` in the background, its progress shows in the model picker.
+fn pull_model(app: &mut App<'_>, model: &str, sender: &UnboundedSender<Event>) {
+    if model.is_empty() {
+        let notif = Notification::new("Usage: /pull <model>".to_string(), NotificationLevel::Error);
+        sender.send(Event::Notification(notif)).unwrap();
+        return;
+    }
+    let Some(api) = ollama_api(app, sender) else {
+        return;
+    };
+
+    let model = model.to_string();
+    let sender = sender.clone();
+    tokio::spawn(async move {
+        let notif = match api.pull(&model, &sender).await {
+            Ok(()) => Notification::new(format!("Pulled `{}`", model), NotificationLevel::Info),
+            Err(e) => Notification::new(
+                format!("Pulling `{}` failed: {}", model, e),
+                NotificationLevel::Error,
+            ),
+        };
+        let _ = sender.send(Event::Models(ModelEvent::Pull(None)));
+        let _ = sender.send(Event::Notification(notif));
+        list_models(&api, &sender).await;
+    });
+}
+
+/// Frees the memory `model` takes on the Ollama server.
+fn unload_model(app: &mut App<'_>, model: &str, sender: &UnboundedSender<Event>) {
+    let Some(api) = ollama_api(app, sender) else {
+        return;
+    };
+
+    let model = model.to_string();
+    let sender = sender.clone();
+    tokio::spawn(async move {
+        let notif = match api.unload(&model, &sender).await {
+            Ok(()) => Notification::new(format!("Unloaded `{}`", model), NotificationLevel::Info),
+            Err(e) => Notification::new(e.to_string(), NotificationLevel::Error),
+        };
+        let _ = sender.send(Event::Notification(notif));
+        list_models(&api, &sender).await;
+    });
+}
+
+/// Keeps the active Ollama profile, with `model` instead of its configured one.
+fn switch_model(
+    app: &mut App<'_>,
+    llm: &mut Arc<dyn LLM>,
+    model: &str,
+    sender: &UnboundedSender<Event>,
+) {
+    let config = app.config.clone();
+    let Some(profile) = config.profile(&app.llm_profile) else {
+        return;
+    };
+    let profile = Profile {
+        model: Some(model.to_string()),
+        ..profile.clone()
+    };
+
+    let notif = match Ollama::new(&profile, &config) {
+        Ok(next) => {
+            *llm = Arc::new(next);
+            app.set_llm(&profile.name, llm.as_ref());
+            Notification::new(
+                format!("Switched to {}", app.llm_label()),
+                NotificationLevel::Info,
+            )
+        }
+        Err(e) => Notification::new(e.to_string(), NotificationLevel::Error),
+    };
+
+    sender.send(Event::Notification(notif)).unwrap();
+}
+
+/// Pins the last user message, so it stays in every request of the conversation.
+fn pin_last_message(app: &mut App<'_>, sender: &UnboundedSender<Event>) {
+    let Some(message) = app
+        .chat
+        .messages
+        .iter_mut()
+        .rev()
+        .find(|m| m.role == LLMRole::USER)
+    else {
+        return;
+    };
+
+    message.pinned = true;
+    let notif = Notification::new(
+        "Pinned the last message".to_string(),
+        NotificationLevel::Info,
+    );
+
+    sender.send(Event::Notification(notif)).unwrap();
+}
@@ -1,6 +1,9 @@
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::app::AppResult;
+use crate::config::Config;
+use crate::llm::ollama::ModelEvent;
 use crate::llm::LLMAnswer;
 use crate::ui::Notification;
 use crossterm::event::{Event as CrosstermEvent, KeyEvent, MouseEvent};
@@ -8,7 +11,9 @@ use futures::{FutureExt, StreamExt};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 mod handler;
-pub use handler::handle_key_events;
+mod watcher;
+pub use handler::{apply_config, handle_key_events, send_queued};
+pub use watcher::watch_config;
 
 #[derive(Clone, Debug)]
 pub enum Event {
@@ -17,7 +22,13 @@ pub enum Event {
     Mouse(MouseEvent),
     Resize(u16, u16),
     LLMEvent(LLMAnswer),
+    /// An event of the answer with this id, dropped once that answer was abandoned.
+    Answer(u64, LLMAnswer),
+    /// Ollama model listing and pull progress, for the model picker.
+    Models(ModelEvent),
     Notification(Notification),
+    /// `config.toml` was edited and parsed again.
+    Config(Arc<Config>),
 }
 
 #[allow(dead_code)]
@@ -78,9 +89,6 @@ impl EventHandler {
         self.receiver
             .recv()
             .await
-            .ok_or(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "This is an IO error",
-            )))
+            .ok_or(Box::new(std::io::Error::other("This is an IO error")))
     }
 }
@@ -0,0 +1,97 @@
+//! Polls the config directory, so edits to `config.toml` and to the prompt
+//! presets apply without a restart.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::Event;
+use crate::config::Config;
+use crate::llm::preset::{self, DEFAULT_PRESET};
+use crate::ui::{Notification, NotificationLevel};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Modification times of the files read from `dir`.
+fn snapshot(dir: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let presets = fs::read_dir(dir.join("prompts"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"));
+
+    [dir.join("config.toml"), dir.join("prompt.toml")]
+        .into_iter()
+        .chain(presets)
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .collect()
+}
+
+/// Sends the config read again after `config.toml` changed, and a
+/// notification for each changed or removed preset. Files that don't parse
+/// are reported and the previous settings stay in effect.
+pub fn watch_config(dir: PathBuf, sender: UnboundedSender<Event>) {
+    tokio::spawn(async move {
+        let mut known = snapshot(&dir);
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let current = snapshot(&dir);
+            let removed = known.keys().filter(|path| !current.contains_key(*path));
+            let changed: Vec<&PathBuf> = current
+                .iter()
+                .filter(|(path, modified)| known.get(*path) != Some(modified))
+                .map(|(path, _)| path)
+                .chain(removed)
+                .collect();
+
+            for path in changed {
+                let event = if path.ends_with("config.toml") {
+                    match Config::read(path) {
+                        Ok(config) => Event::Config(Arc::new(config)),
+                        Err(e) => Event::Notification(Notification::new(
+                            format!("Keeping the previous config, {}", e),
+                            NotificationLevel::Error,
+                        )),
+                    }
+                } else {
+                    let name = if path.parent() == Some(dir.as_path()) {
+                        // `prompt.toml` is only read without a `prompts/default.toml`
+                        if dir.join("prompts").join("default.toml").exists() {
+                            continue;
+                        }
+                        DEFAULT_PRESET.to_string()
+                    } else {
+                        let Some(stem) = path.file_stem() else {
+                            continue;
+                        };
+                        stem.to_string_lossy().to_string()
+                    };
+                    let notif = match preset::reload(&dir, &name) {
+                        Ok(_) => Notification::new(
+                            format!("Prompt preset `{}` reloaded", name),
+                            NotificationLevel::Info,
+                        ),
+                        Err(_) if !current.contains_key(path) => Notification::new(
+                            format!("Prompt preset `{}` was removed", name),
+                            NotificationLevel::Warning,
+                        ),
+                        Err(e) => Notification::new(e, NotificationLevel::Error),
+                    };
+                    Event::Notification(notif)
+                };
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+            known = current;
+        }
+    });
+}
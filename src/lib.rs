@@ -1,6 +1,3 @@
-#[macro_use]
-extern crate rust_i18n;
-
 pub mod app;
 pub mod config;
 pub mod event;
@@ -11,16 +8,16 @@ pub mod ui;
 
 pub use crate::app::{App, AppResult};
 pub use crate::config::Config;
-pub use crate::event::{handle_key_events, Event, EventHandler};
+pub use crate::event::{apply_config, handle_key_events, send_queued, Event, EventHandler};
 pub use crate::ui::{Formatter, Tui};
 
-pub use crate::llm::{LLMAnswer, LLMModel, LLMRole, LLM};
+pub use crate::llm::{LLMAnswer, LLMError, LLMModel, LLMRole, LLM};
 
 use clap::{Arg, Command};
 
-use rust_i18n::{i18n, locale};
+use rust_i18n::i18n;
 
-i18n!("locales", fallback="en");
+i18n!("locales", fallback = "en");
 
 pub fn set_language(lang: &str) {
     rust_i18n::set_locale(lang);
@@ -30,10 +27,12 @@ pub fn set_language(lang: &str) {
 
 pub fn cli() -> Command {
     Command::new("infini")
-            .about("TUI LLM Chat for InfiniLM")
-            .arg(Arg::new("lang")
+        .about("TUI LLM Chat for InfiniLM")
+        .arg(
+            Arg::new("lang")
                 .short('l')
                 .long("lang")
                 .value_name("LANGUAGE")
-                .help("Sets the display language (e.g., en, zh-CN)"))
+                .help("Sets the display language (e.g., en, zh-CN)"),
+        )
 }
@@ -0,0 +1,266 @@
+use super::*;
+
+use self::context::ContextBudget;
+use self::sse::SseDecoder;
+use crate::config::{AnthropicConfig, GenerationConfig, Profile};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// The Messages API rejects requests without `max_tokens`.
+const DEFAULT_MAX_TOKENS: u32 = 1024;
+
+#[derive(Clone, Debug)]
+pub struct Anthropic {
+    http: net::Http,
+    anthropic_api_key: String,
+    model: String,
+    url: String,
+    generation: GenerationConfig,
+    context: ContextBudget,
+}
+
+impl Anthropic {
+    pub fn new(profile: &Profile, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let anthropic_api_key = match profile.api_key.clone() {
+            Some(key) => key,
+            None => std::env::var("ANTHROPIC_API_KEY").map_err(|_| {
+                r#"Can not find the Anthropic api key
+You need to define one wether in the configuration file or as an environment variable"#
+            })?,
+        };
+
+        Ok(Self {
+            http: net::Http::new(profile)?,
+            anthropic_api_key,
+            model: profile
+                .model
+                .clone()
+                .unwrap_or_else(AnthropicConfig::default_model),
+            url: profile
+                .url
+                .clone()
+                .unwrap_or_else(AnthropicConfig::default_url),
+            generation: profile.generation.or(&config.generation),
+            context: ContextBudget::new(profile, config),
+        })
+    }
+}
+
+#[async_trait]
+impl LLM for Anthropic {
+    fn backend(&self) -> LLMBackend {
+        LLMBackend::Anthropic
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn ask(
+        &self,
+        messages: &[Message],
+        sender: UnboundedSender<Event>,
+    ) -> Result<(), LLMError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "application/json".parse()?);
+        headers.insert("x-api-key", self.anthropic_api_key.parse()?);
+        headers.insert("anthropic-version", ANTHROPIC_VERSION.parse()?);
+
+        let (system, messages) = split_system(&self.context.request_messages(messages, &sender)?);
+
+        let mut body: Value = json!({
+            "model": self.model,
+            "messages": messages,
+            "max_tokens": self.generation.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "stream": true,
+        });
+        set_opt(&mut body, "system", &system);
+        apply_generation(&mut body, &self.generation);
+
+        let request = self
+            .http
+            .client
+            .post(&self.url)
+            .headers(headers)
+            .json(&body);
+
+        let res = error::send(request, &sender).await?;
+        stream_messages(res, self.http.read_timeout, &sender).await
+    }
+}
+
+/// Maps the generation settings onto the Messages request body, which has no
+/// seed or penalties and calls the stop words `stop_sequences`.
+fn apply_generation(body: &mut Value, generation: &GenerationConfig) {
+    set_opt(body, "temperature", &generation.temperature);
+    set_opt(body, "top_p", &generation.top_p);
+    set_opt(body, "top_k", &generation.top_k);
+    set_opt(body, "stop_sequences", &generation.stop);
+}
+
+/// Splits a conversation into the top-level `system` prompt and the message list.
+///
+/// The Messages API only accepts `user` and `assistant` turns, alternating and
+/// starting with `user`. System messages, and assistant turns before the first
+/// user message (e.g. from a prompt preset), go to the system prompt; consecutive
+/// turns of the same role are merged.
+fn split_system(messages: &[Message]) -> (Option<String>, Vec<Value>) {
+    let mut system: Vec<&str> = Vec::new();
+    let mut turns: Vec<(LLMRole, String)> = Vec::new();
+
+    for message in messages {
+        match message.role {
+            LLMRole::SYSTEM => system.push(&message.content),
+            LLMRole::ASSISTANT if turns.is_empty() => system.push(&message.content),
+            role => match turns.last_mut() {
+                Some((last, content)) if *last == role => {
+                    content.push_str("\n\n");
+                    content.push_str(&message.content);
+                }
+                _ => turns.push((role, message.content.clone())),
+            },
+        }
+    }
+
+    let system = (!system.is_empty()).then(|| system.join("\n\n"));
+    let turns = turns
+        .into_iter()
+        .map(|(role, content)| json!({ "role": role, "content": content }))
+        .collect();
+
+    (system, turns)
+}
+
+/// Forwards a Messages API event stream to the UI. A stream cut before
+/// `message_stop` is an error, not a complete answer.
+async fn stream_messages(
+    mut res: reqwest::Response,
+    read_timeout: Option<Duration>,
+    sender: &UnboundedSender<Event>,
+) -> Result<(), LLMError> {
+    let mut decoder = SseDecoder::new();
+    let mut usage = Usage::reported(0, 0);
+
+    sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
+    while let Some(chunk) = net::chunk(&mut res, read_timeout).await? {
+        for event in decoder.feed(&chunk) {
+            let data: Value = serde_json::from_str(&event.data)?;
+            match data["type"].as_str().or(event.event.as_deref()) {
+                Some("content_block_delta") => {
+                    if let Some(text) = data["delta"]["text"].as_str() {
+                        sender.send(Event::LLMEvent(LLMAnswer::Answer(text.to_string())))?;
+                    }
+                }
+                Some("message_start") => {
+                    let reported = &data["message"]["usage"];
+                    usage.prompt_tokens = reported["input_tokens"].as_u64().unwrap_or_default();
+                }
+                Some("message_delta") => {
+                    let reported = &data["usage"]["output_tokens"];
+                    usage.completion_tokens = reported.as_u64().unwrap_or_default();
+                }
+                Some("message_stop") => {
+                    sender.send(Event::LLMEvent(LLMAnswer::Usage(usage)))?;
+                    sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
+                    return Ok(());
+                }
+                Some("error") => {
+                    let message = data["error"]["message"].as_str().unwrap_or("unknown error");
+                    return Err(LLMError::Backend(message.to_string()));
+                }
+                // content_block_start/stop and ping
+                _ => {}
+            }
+        }
+    }
+    decoder.finish();
+
+    Err(LLMError::Network(
+        "the stream ended before `message_stop`".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub;
+    use tokio::sync::mpsc;
+
+    const STREAM: &str = concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"role\":\"assistant\",\"content\":[],\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+        "event: ping\n",
+        "data: {\"type\":\"ping\"}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"你好\"}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\", Rust!\"}}\n\n",
+        "event: content_block_stop\n",
+        "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+        "event: message_delta\n",
+        "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":6}}\n\n",
+        "event: message_stop\n",
+        "data: {\"type\":\"message_stop\"}\n\n",
+    );
+
+    #[test]
+    fn prompts_are_mapped_to_system_and_alternating_turns() {
+        let messages = vec![
+            Message::new(LLMRole::SYSTEM, "You are a Rust expert.".to_string()),
+            Message::new(LLMRole::ASSISTANT, "Think step by step.".to_string()),
+            Message::new(LLMRole::USER, "Hi".to_string()),
+            Message::new(LLMRole::USER, "Explain lifetimes".to_string()),
+            Message::new(LLMRole::ASSISTANT, "Sure.".to_string()),
+        ];
+
+        let (system, turns) = split_system(&messages);
+        assert_eq!(
+            system.as_deref(),
+            Some("You are a Rust expert.\n\nThink step by step.")
+        );
+        assert_eq!(
+            turns,
+            vec![
+                json!({ "role": "user", "content": "Hi\n\nExplain lifetimes" }),
+                json!({ "role": "assistant", "content": "Sure." }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_from_a_local_server() {
+        let (url, server) = stub::serve_once("text/event-stream", STREAM).await;
+        let profile = stub::profile_with(
+            "anthropic",
+            &format!("url = \"{}/v1/messages\"\napi_key = \"test-key\"", url),
+        );
+        let llm = Anthropic::new(&profile, &toml::from_str("").unwrap()).unwrap();
+        let messages = [Message::new(LLMRole::USER, "Hi".to_string())];
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        llm.ask(&messages, sender).await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.head.contains("x-api-key: test-key"));
+        assert_eq!(request.body["messages"][0]["role"], "user");
+        assert_eq!(request.body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(stub::collect_answer(&mut receiver), "你好, Rust!");
+    }
+
+    #[tokio::test]
+    async fn truncated_stream_is_an_error() {
+        let cut = &STREAM[..STREAM.find("event: message_stop").unwrap()];
+        let cut: &'static str = Box::leak(cut.to_string().into_boxed_str());
+        let (url, _server) = stub::serve_once("text/event-stream", cut).await;
+        let res = reqwest::get(url).await.unwrap();
+
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        assert!(matches!(
+            stream_messages(res, None, &sender).await,
+            Err(LLMError::Network(_))
+        ));
+    }
+}
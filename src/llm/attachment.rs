@@ -0,0 +1,106 @@
+//! Local images attached to a user message, for vision-capable models.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use super::*;
+
+/// Larger images are refused, most APIs reject them anyway.
+const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// A local image attached to a message, read once when attached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    pub path: PathBuf,
+    pub mime: &'static str,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// The file, base64 encoded.
+    pub data: Arc<str>,
+}
+
+impl Attachment {
+    pub fn image(path: &Path) -> Result<Self, String> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.display().to_string());
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let mime = match extension.as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            _ => return Err(format!("`{}` is not a png, jpeg, gif or webp image", name)),
+        };
+
+        let size = fs::metadata(path)
+            .map_err(|e| format!("{}: {}", name, e))?
+            .len();
+        if size > MAX_IMAGE_BYTES {
+            return Err(format!(
+                "`{}` is larger than {}",
+                name,
+                human_size(MAX_IMAGE_BYTES)
+            ));
+        }
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", name, e))?;
+
+        Ok(Self {
+            name,
+            path: path.to_path_buf(),
+            mime,
+            size,
+            data: STANDARD.encode(bytes).into(),
+        })
+    }
+
+    /// The placeholder shown in the chat, e.g. `🖼 cat.png (12.3 KB)`.
+    pub fn label(&self) -> String {
+        format!("🖼 {} ({})", self.name, human_size(self.size))
+    }
+
+    /// The image inlined as a `data:` url, as OpenAI-style APIs take it.
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime, self.data)
+    }
+}
+
+/// `bytes` in B, KB, MB or GB, one decimal above a kilobyte.
+pub fn human_size(bytes: u64) -> String {
+    const KB: f64 = 1024.0;
+    match bytes as f64 {
+        b if b < KB => format!("{} B", bytes),
+        b if b < KB * KB => format!("{:.1} KB", b / KB),
+        b if b < KB * KB * KB => format!("{:.1} MB", b / (KB * KB)),
+        b => format!("{:.1} GB", b / (KB * KB * KB)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::TempDir;
+
+    #[test]
+    fn images_are_read_and_encoded() {
+        let dir = TempDir::new();
+        let png = dir.join("dot.PNG");
+        fs::write(&png, b"\x89PNG\r\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not an image").unwrap();
+
+        let image = Attachment::image(&png).unwrap();
+        assert_eq!(image.label(), "🖼 dot.PNG (6 B)");
+        assert_eq!(image.data_url(), "data:image/png;base64,iVBORw0K");
+        assert!(Attachment::image(&dir.join("notes.txt")).is_err());
+        assert!(Attachment::image(&dir.join("missing.png")).is_err());
+    }
+}
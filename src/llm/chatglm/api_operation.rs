@@ -1,14 +1,24 @@
-use once_cell::sync::OnceCell;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
 
 const API_KEY_FILE: &str = "chatglm_api_key.txt";
 
+/// A ChatGLM api key, `<id>.<secret>`.
+#[derive(Clone, PartialEq)]
 pub struct APIKeys {
     user_id: String,
     user_secret: String,
 }
 
+impl std::fmt::Debug for APIKeys {
+    /// Leaves the secret out of logs.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("APIKeys")
+            .field("user_id", &self.user_id)
+            .finish_non_exhaustive()
+    }
+}
+
 impl APIKeys {
     fn new(user_id: &str, user_secret: &str) -> APIKeys {
         APIKeys {
@@ -17,17 +27,13 @@ impl APIKeys {
         }
     }
 
-    pub fn get_instance(api: &str) -> &APIKeys {
-        static INSTANCE: OnceCell<APIKeys> = OnceCell::new();
-
-        INSTANCE.get_or_init(|| {
-            let parts: Vec<&str> = api.trim().split('.').collect();
-            if parts.len() == 2 {
-                APIKeys::new(parts[0], parts[1])
-            } else {
-                panic!("Your API Key is Invalid");
+    pub fn parse(api: &str) -> Result<APIKeys, String> {
+        match api.trim().split_once('.') {
+            Some((id, secret)) if !id.is_empty() && !secret.is_empty() && !secret.contains('.') => {
+                Ok(APIKeys::new(id, secret))
             }
-        })
+            _ => Err("Your ChatGLM API key is invalid, it should look like `<id>.<secret>`".into()),
+        }
     }
 
     pub fn get_user_id(&self) -> &str {
@@ -1,29 +1,35 @@
 use base64url::encode;
-use chrono::{DateTime, Local};
 use hmac::{Hmac, Mac};
-use rsntp::SntpClient;
+use serde_json::json;
 use sha2::Sha256;
 
+const HEADER: &str = "{\"alg\":\"HS256\",\"sign_type\":\"SIGN\"}";
+
+/// Signs the HS256 tokens ChatGLM takes as bearer, from the two halves of an api key.
 pub struct CustomJwt {
+    user_id: String,
     secret: String,
-    header: String,
-    payload: String,
 }
 
 impl CustomJwt {
     pub fn new(user_id: &str, user_secret: &str) -> CustomJwt {
-        let header = "{\"alg\":\"HS256\",\"sign_type\":\"SIGN\"}".to_string();
-        let payload = CustomJwt::jwt_payload(user_id);
         CustomJwt {
+            user_id: user_id.to_string(),
             secret: user_secret.to_string(),
-            header,
-            payload,
         }
     }
 
-    pub fn create_jwt(&self) -> String {
-        let encoded_header = CustomJwt::encode_base64_url(self.header.as_bytes());
-        let encoded_payload = CustomJwt::encode_base64_url(self.payload.as_bytes());
+    /// A token issued at `now_ms` and expiring `ttl_ms` later, both in milliseconds.
+    pub fn create_jwt(&self, now_ms: i64, ttl_ms: i64) -> String {
+        let payload = json!({
+            "api_key": self.user_id,
+            "exp": now_ms + ttl_ms,
+            "timestamp": now_ms,
+        })
+        .to_string();
+
+        let encoded_header = CustomJwt::encode_base64_url(HEADER.as_bytes());
+        let encoded_payload = CustomJwt::encode_base64_url(payload.as_bytes());
         let to_sign = format!("{}.{}", encoded_header, encoded_payload);
 
         let signature_bytes = self.generate_signature(&to_sign);
@@ -50,15 +56,6 @@ impl CustomJwt {
         calculated_signature == signature
     }
 
-    fn jwt_payload(user_id: &str) -> String {
-        let time_now = time_sync();
-        let exp_time = time_now * 3;
-        format!(
-            "{{\"api_key\":\"{}\",\"exp\":{},\"timestamp\":{}}}",
-            user_id, exp_time, time_now
-        )
-    }
-
     fn generate_signature(&self, data: &str) -> Vec<u8> {
         let mut hmac =
             Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).expect("HMAC key error");
@@ -71,14 +68,28 @@ impl CustomJwt {
     }
 }
 
-fn time_sync() -> i64 {
-    let client = SntpClient::new();
-    // FIXME: 国际服务和国内服务无缝切换
-    let result = client.synchronize("ntp.aliyun.com").unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let local_time: DateTime<Local> =
-        DateTime::from(result.datetime().into_chrono_datetime().unwrap());
+    use serde_json::Value;
 
-    let milliseconds = local_time.timestamp_millis() as i64;
-    return milliseconds;
+    #[test]
+    fn tokens_are_signed_with_their_lifetime() {
+        let jwt = CustomJwt::new("id", "secret");
+        let token = jwt.create_jwt(1_700_000_000_000, 3_600_000);
+
+        let parts: Vec<&str> = token.split('.').collect();
+        let header: Value = serde_json::from_slice(&base64url::decode(parts[0]).unwrap()).unwrap();
+        let payload: Value = serde_json::from_slice(&base64url::decode(parts[1]).unwrap()).unwrap();
+        assert_eq!(header["sign_type"], "SIGN");
+        assert_eq!(payload["api_key"], "id");
+        assert_eq!(payload["timestamp"], 1_700_000_000_000i64);
+        assert_eq!(payload["exp"], 1_700_003_600_000i64);
+
+        assert!(jwt.verify_jwt(&token));
+        assert!(!CustomJwt::new("id", "other").verify_jwt(&token));
+        let tampered = token.replacen(parts[1], &encode(b"{\"api_key\":\"x\"}"), 1);
+        assert!(!jwt.verify_jwt(&tampered));
+    }
 }
@@ -1,92 +1,105 @@
 use super::*;
 
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tokio::time::{sleep, Duration};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::config::ChatGLMConfig;
-use regex::Regex;
+use crate::config::{ChatGLMConfig, GenerationConfig, Profile};
+use crate::llm::context::ContextBudget;
 
-use reqwest::header::HeaderValue;
-use reqwest::Client;
+use self::api_operation::APIKeys;
+use self::token::TokenSigner;
 
 pub mod api_operation;
 pub mod custom_jwt;
+pub mod token;
 
 #[derive(Clone, Debug)]
 pub struct ChatGLM {
-    client: reqwest::Client,
-    chatglm_api_key: String,
+    http: net::Http,
+    signer: Arc<Mutex<TokenSigner>>,
+    ntp_server: Option<String>,
+    /// Offset of the local clock to `ntp_server`, measured once.
+    clock_skew: Arc<tokio::sync::OnceCell<i64>>,
     model: String,
     url: String,
-    messages: Vec<HashMap<String, String>>,
+    generation: GenerationConfig,
+    context: ContextBudget,
 }
 
 impl ChatGLM {
-    pub fn new(config: ChatGLMConfig) -> Self {
-        let chatglm_api_key = match std::env::var("CHATML_API_KEY") {
-            Ok(key) => key,
-            Err(_) => config
-                .chatglm_api_key
-                .ok_or_else(|| {
-                    eprintln!(
-                        r#"Can not find the ChatGLM api key
-You need to define one wether in the configuration file or as an environment variable"#
-                    );
-
-                    std::process::exit(1);
-                })
-                .unwrap(),
+    pub fn new(profile: &Profile, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let chatglm_api_key = match profile.api_key.clone() {
+            Some(key) => Some(key),
+            None => std::env::var("CHATML_API_KEY").ok(),
         };
-
-        Self {
-            client: reqwest::Client::new(),
-            chatglm_api_key,
-            model: config.model,
-            url: config.url,
-            messages: Vec::new(),
+        let keys = chatglm_api_key
+            .iter()
+            .chain(&profile.chatglm.api_keys)
+            .map(|key| APIKeys::parse(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err(r#"Can not find the ChatGLM api key
+You need to define one wether in the configuration file or as an environment variable"#
+                .into());
         }
+        let ttl = profile
+            .chatglm
+            .token_ttl
+            .map(Duration::from_secs)
+            .unwrap_or(token::DEFAULT_TTL);
+
+        Ok(Self {
+            http: net::Http::new(profile)?,
+            signer: Arc::new(Mutex::new(TokenSigner::new(&keys, ttl))),
+            ntp_server: profile.chatglm.ntp_server.clone(),
+            clock_skew: Arc::default(),
+            model: profile
+                .model
+                .clone()
+                .unwrap_or_else(ChatGLMConfig::default_model),
+            url: profile
+                .url
+                .clone()
+                .unwrap_or_else(ChatGLMConfig::default_url),
+            generation: profile.generation.or(&config.generation),
+            context: ContextBudget::new(profile, config),
+        })
     }
 
-    pub fn sign_token(&self) -> String {
-        let api_key_instance = api_operation::APIKeys::get_instance(&self.chatglm_api_key);
-        let jwt_creator = custom_jwt::CustomJwt::new(
-            api_key_instance.get_user_id(),
-            api_key_instance.get_user_secret(),
-        );
-        let jwt = jwt_creator.create_jwt();
-
-        let jwt_to_verify = jwt.clone();
-        let is_valid = jwt_creator.verify_jwt(&jwt_to_verify);
-
-        if is_valid {
-            return jwt;
-        } else {
-            panic!("JWT is not valid");
-        }
+    /// A bearer token for the next request, from the cache when still fresh.
+    pub async fn sign_token(&self) -> String {
+        let skew = match &self.ntp_server {
+            Some(server) => {
+                *self
+                    .clock_skew
+                    .get_or_init(|| token::clock_skew(server))
+                    .await
+            }
+            None => 0,
+        };
+        self.signer
+            .lock()
+            .unwrap()
+            .token(token::local_now_ms() + skew)
     }
 }
 
 #[async_trait]
 impl LLM for ChatGLM {
-    fn clear(&mut self) {
-        self.messages = Vec::new();
+    fn backend(&self) -> LLMBackend {
+        LLMBackend::ChatGLM
     }
 
-    fn append_chat_msg(&mut self, msg: String, role: LLMRole) {
-        let mut conv: HashMap<String, String> = HashMap::new();
-        conv.insert("role".to_string(), role.to_string());
-        conv.insert("content".to_string(), msg);
-        self.messages.push(conv);
+    fn model(&self) -> &str {
+        &self.model
     }
 
     async fn ask(
         &self,
+        messages: &[Message],
         sender: UnboundedSender<Event>,
-        terminate_response_signal: Arc<AtomicBool>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let jwt = self.sign_token();
+    ) -> Result<(), LLMError> {
+        let jwt = self.sign_token().await;
         let mut headers = HeaderMap::new();
         headers.insert("Content-Type", "application/json".parse()?);
         headers.insert("Authorization", format!("Bearer {}", jwt).parse()?);
@@ -101,136 +114,46 @@ impl LLM for ChatGLM {
         //     ])),
         // ];
 
-        let mut messages: Vec<HashMap<String, String>> = super::read_default_prompts();
-
-        messages.extend(self.messages.clone());
+        let messages = self.context.request_messages(messages, &sender)?;
 
-        let body: Value = json!({
+        let mut body: Value = json!({
             "model": self.model,
             "messages": messages,
             "stream": true,
         });
+        apply_generation(&mut body, &self.generation);
 
-        // FIXME: support sse
-
-        let response = self
+        let request = self
+            .http
             .client
             .post(&self.url)
             .headers(headers)
-            .json(&body)
-            .send()
-            .await?;
-
-        match response.error_for_status() {
-            Ok(mut res) => {
-                let mut last_string = String::new();
-                sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
-                while let Some(chunk) = res.chunk().await? {
-                    let mut chunk = std::str::from_utf8(&chunk)?.to_owned();
-                    if !last_string.is_empty() {
-                        chunk = last_string + &chunk;
-                        last_string = "".into();
-                    }
-                    // info!("ChatGLM response chunk: {}", chunk);
-
-                    let re = Regex::new(r"data:\s(.*)")?;
-
-                    for captures in re.captures_iter(&chunk) {
-                        if let Some(data_json) = captures.get(1) {
-                            // info!("ChatGLM response data json: {:?} ", data_json);
-
-                            if terminate_response_signal.load(Ordering::Relaxed) {
-                                sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
-                                return Ok(());
-                            }
-
-                            if data_json.as_str() == "[DONE]" {
-                                sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
-                                return Ok(());
-                            }
-
-                            // FIX EOF
-                            let answer: Value = match serde_json::from_str(data_json.as_str()) {
-                                Ok(answer) => answer,
-                                Err(_e) => {
-                                    last_string = data_json.as_str().to_string();
-                                    continue;
-                                }
-                            };
-
-                            let msg = answer["choices"][0]["delta"]["content"].as_str();
-                            let mut queue_result = String::new();
-                            let mut char_queue = VecDeque::new();
-
-                            if let Some(content) = msg {
-                                let get_message = convert_unicode_emojis(content)
-                                    .replace("\"", "")
-                                    .replace("\\n\\n", "\n")
-                                    .replace("\\nn", "\n")
-                                    .replace("\\\\n", "\n")
-                                    .replace("\\\\nn", "\n")
-                                    .replace("\\", "");
-
-                                for c in get_message.chars() {
-                                    char_queue.push_back(c);
-                                }
-                            } else {
-                                println!("Invalid JSON format");
-                            }
-
-                            queue_result.extend(char_queue);
-
-                            if queue_result != "null" {
-                                sender.send(Event::LLMEvent(LLMAnswer::Answer(queue_result)))?;
-                            }
-
-                            sleep(Duration::from_millis(100)).await;
-                        }
-                    }
+            .json(&body);
+
+        let res = match error::send(request, &sender).await {
+            Ok(res) => res,
+            Err(e) => {
+                if matches!(e, LLMError::Auth(_)) {
+                    self.signer.lock().unwrap().invalidate(&jwt);
                 }
+                return Err(e);
             }
-            Err(e) => return Err(Box::new(e)),
-        }
+        };
+        sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
+        sse::stream_openai(res, self.http.read_timeout, &sender).await?;
+        sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
 
         Ok(())
     }
 }
 
-lazy_static::lazy_static! {
-    static ref UNICODE_REGEX: regex::Regex = regex::Regex::new(r"\\u[0-9a-fA-F]{4}").unwrap();
-}
-
-fn convert_unicode_emojis(input: &str) -> String {
-    UNICODE_REGEX
-        .replace_all(input, |caps: &regex::Captures| {
-            let emoji = char::from_u32(
-                u32::from_str_radix(&caps[0][2..], 16).expect("Failed to parse Unicode escape"),
-            )
-            .expect("Invalid Unicode escape");
-            emoji.to_string()
-        })
-        .to_string()
-}
-
-/// use default_josn to avoid EOF error
-#[allow(dead_code)]
-fn default_json() -> Value {
-    json!(
-        {
-            "choices": [
-              {
-                "delta": {
-                  "content": "!",
-                  "role": "assistant"
-                },
-                "index": 0
-              }
-            ],
-            "created": 1711946953,
-            "id": "8530388547035158662",
-            "model": "glm-4"
-          }
-    )
+/// Maps the generation settings onto the ChatGLM request body, which only
+/// knows the sampling and length parameters.
+fn apply_generation(body: &mut Value, generation: &GenerationConfig) {
+    set_opt(body, "temperature", &generation.temperature);
+    set_opt(body, "top_p", &generation.top_p);
+    set_opt(body, "max_tokens", &generation.max_tokens);
+    set_opt(body, "stop", &generation.stop);
 }
 
 // // for test
@@ -0,0 +1,156 @@
+//! Bearer tokens of the ChatGLM requests. A token is signed per api key and
+//! reused until shortly before it expires; several keys are used in turn.
+
+use std::time::Duration;
+
+use chrono::Local;
+use rsntp::AsyncSntpClient;
+use tracing::warn;
+
+use super::api_operation::APIKeys;
+use super::custom_jwt::CustomJwt;
+
+/// Lifetime of a token when the profile sets no `token_ttl`.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(3600);
+/// A token is replaced this long before it expires, so it can't expire in flight.
+const REFRESH_MARGIN_MS: i64 = 60_000;
+/// The NTP query gives up after this, the local clock is used then.
+const NTP_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct Cached {
+    token: String,
+    expires_ms: i64,
+}
+
+struct Key {
+    jwt: CustomJwt,
+    user_id: String,
+    cached: Option<Cached>,
+}
+
+pub struct TokenSigner {
+    keys: Vec<Key>,
+    next: usize,
+    ttl_ms: i64,
+}
+
+impl std::fmt::Debug for TokenSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenSigner")
+            .field(
+                "keys",
+                &self.keys.iter().map(|key| &key.user_id).collect::<Vec<_>>(),
+            )
+            .field("ttl_ms", &self.ttl_ms)
+            .finish()
+    }
+}
+
+impl TokenSigner {
+    pub fn new(keys: &[APIKeys], ttl: Duration) -> Self {
+        Self {
+            keys: keys
+                .iter()
+                .map(|key| Key {
+                    jwt: CustomJwt::new(key.get_user_id(), key.get_user_secret()),
+                    user_id: key.get_user_id().to_string(),
+                    cached: None,
+                })
+                .collect(),
+            next: 0,
+            ttl_ms: ttl.as_millis().try_into().unwrap_or(i64::MAX / 2),
+        }
+    }
+
+    /// The token of the next key in turn at `now_ms`, signed again when it
+    /// is about to expire.
+    pub fn token(&mut self, now_ms: i64) -> String {
+        let margin = REFRESH_MARGIN_MS.min(self.ttl_ms / 2);
+        let ttl_ms = self.ttl_ms;
+        let index = self.next;
+        self.next = (index + 1) % self.keys.len();
+        let key = &mut self.keys[index];
+
+        match &key.cached {
+            Some(cached) if now_ms < cached.expires_ms - margin => cached.token.clone(),
+            _ => {
+                let token = key.jwt.create_jwt(now_ms, ttl_ms);
+                key.cached = Some(Cached {
+                    token: token.clone(),
+                    expires_ms: now_ms + ttl_ms,
+                });
+                token
+            }
+        }
+    }
+
+    /// Drops `token` from the cache after the server refused it.
+    pub fn invalidate(&mut self, token: &str) {
+        for key in &mut self.keys {
+            if key.cached.as_ref().is_some_and(|c| c.token == token) {
+                key.cached = None;
+            }
+        }
+    }
+}
+
+/// Milliseconds since the epoch on the local clock.
+pub fn local_now_ms() -> i64 {
+    Local::now().timestamp_millis()
+}
+
+/// How far the local clock is behind `server`, in milliseconds. Errors are
+/// logged and read as no skew.
+pub async fn clock_skew(server: &str) -> i64 {
+    let client = AsyncSntpClient::new();
+    match tokio::time::timeout(NTP_TIMEOUT, client.synchronize(server)).await {
+        Ok(Ok(result)) => (result.clock_offset().as_secs_f64() * 1000.0) as i64,
+        Ok(Err(e)) => {
+            warn!(
+                "NTP query to {} failed, using the local clock: {}",
+                server, e
+            );
+            0
+        }
+        Err(_) => {
+            warn!("NTP query to {} timed out, using the local clock", server);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_reused_until_they_expire_and_keys_rotate() {
+        let keys = [
+            APIKeys::parse("first.secret").unwrap(),
+            APIKeys::parse("second.secret").unwrap(),
+        ];
+        assert!(APIKeys::parse("no-secret").is_err());
+        assert!(APIKeys::parse("a.b.c").is_err());
+
+        let mut signer = TokenSigner::new(&keys, Duration::from_secs(600));
+        let now = 1_700_000_000_000;
+        let first = signer.token(now);
+        let second = signer.token(now);
+        assert_ne!(first, second);
+        assert!(CustomJwt::new("first", "secret").verify_jwt(&first));
+        assert!(CustomJwt::new("second", "secret").verify_jwt(&second));
+
+        // Reused while far from expiring
+        assert_eq!(signer.token(now + 500_000), first);
+        assert_eq!(signer.token(now + 500_000), second);
+
+        // Signed again within the margin
+        let renewed = signer.token(now + 550_000);
+        assert_ne!(renewed, first);
+        assert_ne!(signer.token(now + 550_000), second);
+
+        // A refused token is not sent again
+        signer.invalidate(&renewed);
+        assert_ne!(signer.token(now + 560_000), renewed);
+    }
+}
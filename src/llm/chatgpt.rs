@@ -4,149 +4,215 @@ use reqwest::header::HeaderValue;
 use reqwest::Client;
 
 // use async_openai::{Client, types::{CreateChatCompletionResponse, CreateChatCompletionRequest, ChatCompletionRequestMessage, Role, CreateEmbeddingRequest, EmbeddingInput}};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tokio::time::{sleep, Duration};
 
-use crate::config::ChatGPTConfig;
-use regex::Regex;
+use crate::config::{ChatGPTConfig, GenerationConfig, Profile};
+use crate::llm::context::ContextBudget;
+use crate::llm::tools::{ToolFormat, ToolLoop, Tools};
 
+/// Client for the OpenAI chat completions protocol, used by the `chatgpt` and
+/// `openai_compatible` backends.
 #[derive(Clone, Debug)]
 pub struct ChatGPT {
-    client: reqwest::Client,
-    openai_api_key: String,
-    model: String,
+    http: net::Http,
+    backend: LLMBackend,
+    openai_api_key: Option<String>,
+    model: Option<String>,
     url: String,
-    messages: Vec<HashMap<String, String>>,
+    headers: HeaderMap,
+    query: Vec<(String, String)>,
+    extra_body: Option<Value>,
+    generation: GenerationConfig,
+    context: ContextBudget,
+    tools: Option<Tools>,
 }
 
 impl ChatGPT {
-    pub fn new(config: ChatGPTConfig) -> Self {
-        let openai_api_key = match std::env::var("OPENAI_API_KEY") {
-            Ok(key) => key,
-            Err(_) => config
-                .openai_api_key
-                .ok_or_else(|| {
-                    eprintln!(
-                        r#"Can not find the openai api key
+    pub fn new(profile: &Profile, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let openai_api_key = match profile.api_key.clone() {
+            Some(key) => key,
+            None => std::env::var("OPENAI_API_KEY").map_err(|_| {
+                r#"Can not find the openai api key
 You need to define one wether in the configuration file or as an environment variable"#
-                    );
-
-                    std::process::exit(1);
-                })
-                .unwrap(),
+            })?,
         };
 
-        Self {
-            client: reqwest::Client::new(),
-            openai_api_key,
-            model: config.model,
-            url: config.url,
-            messages: Vec::new(),
+        Ok(Self {
+            backend: LLMBackend::ChatGPT,
+            openai_api_key: Some(openai_api_key),
+            model: Some(
+                profile
+                    .model
+                    .clone()
+                    .unwrap_or_else(ChatGPTConfig::default_model),
+            ),
+            url: profile
+                .url
+                .clone()
+                .unwrap_or_else(ChatGPTConfig::default_url),
+            ..Self::compatible_parts(profile, config)?
+        })
+    }
+
+    /// Any OpenAI-style server (vLLM, SGLang, LM Studio, gateways): the url is
+    /// required, the api key and model are optional.
+    pub fn compatible(
+        profile: &Profile,
+        config: &Config,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let url = profile
+            .url
+            .clone()
+            .ok_or_else(|| format!("Profile `{}` needs a url", profile.name))?;
+
+        Ok(Self {
+            url,
+            ..Self::compatible_parts(profile, config)?
+        })
+    }
+
+    fn compatible_parts(
+        profile: &Profile,
+        config: &Config,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &profile.openai.headers {
+            headers.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())?,
+                value.parse()?,
+            );
+        }
+
+        Ok(Self {
+            http: net::Http::new(profile)?,
+            backend: LLMBackend::OpenAICompatible,
+            openai_api_key: profile.api_key.clone(),
+            model: profile.model.clone(),
+            url: String::new(),
+            headers,
+            query: profile.openai.query.clone().into_iter().collect(),
+            extra_body: profile.openai.extra_body.clone(),
+            generation: profile.generation.or(&config.generation),
+            context: ContextBudget::new(profile, config),
+            tools: profile
+                .tools
+                .then(|| Tools::new(&config.file_explorer_path)),
+        })
+    }
+
+    fn request_body(&self, messages: &[Value]) -> Value {
+        let mut body: Value = json!({
+            "messages": messages,
+            "stream": true,
+        });
+        set_opt(&mut body, "model", &self.model);
+        if self.backend == LLMBackend::ChatGPT {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        if let Some(tools) = &self.tools {
+            body["tools"] = tools.definitions();
         }
+        apply_generation(&mut body, &self.generation);
+        if let Some(Value::Object(extra)) = &self.extra_body {
+            for (key, value) in extra {
+                body[key] = value.clone();
+            }
+        }
+        body
     }
 }
 
 #[async_trait]
 impl LLM for ChatGPT {
-    fn clear(&mut self) {
-        self.messages = Vec::new();
+    fn backend(&self) -> LLMBackend {
+        self.backend
+    }
+
+    fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or("default")
     }
 
-    fn append_chat_msg(&mut self, msg: String, role: LLMRole) {
-        let mut conv: HashMap<String, String> = HashMap::new();
-        conv.insert("role".to_string(), role.to_string());
-        conv.insert("content".to_string(), msg);
-        self.messages.push(conv);
+    fn supports_images(&self) -> bool {
+        true
     }
 
     // For UI
     async fn ask(
         &self,
+        messages: &[Message],
         sender: UnboundedSender<Event>,
-        terminate_response_signal: Arc<AtomicBool>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), LLMError> {
         let mut headers = HeaderMap::new();
         headers.insert("Content-Type", "application/json".parse()?);
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", self.openai_api_key).parse()?,
-        );
-
-        let mut messages: Vec<HashMap<String, String>> = super::read_default_prompts();
-
-        messages.extend(self.messages.clone());
-
-        let body: Value = json!({
-            "model": self.model,
-            "messages": messages,
-            "stream": true,
-            "temperature": 0.1,
-        });
-
-        let response = self
-            .client
-            .post(&self.url)
-            .headers(headers)
-            .json(&body)
-            .send()
-            .await?;
-
-        match response.error_for_status() {
-            Ok(mut res) => {
-                let mut last_string = String::new();
-
+        if let Some(key) = &self.openai_api_key {
+            headers.insert("Authorization", format!("Bearer {}", key).parse()?);
+        }
+        headers.extend(self.headers.clone());
+
+        let mut messages = self
+            .context
+            .request_messages(messages, &sender)?
+            .iter()
+            .map(request_message)
+            .collect::<Result<Vec<Value>, _>>()?;
+        let mut tool_loop = self
+            .tools
+            .as_ref()
+            .map(|tools| ToolLoop::new(tools, ToolFormat::OpenAI));
+
+        for round in 0.. {
+            let request = self
+                .http
+                .client
+                .post(&self.url)
+                .query(&self.query)
+                .headers(headers.clone())
+                .json(&self.request_body(&messages));
+
+            let res = error::send(request, &sender).await?;
+            if round == 0 {
                 sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
-                while let Some(chunk) = res.chunk().await? {
-                    let mut chunk = std::str::from_utf8(&chunk)?.to_owned();
-                    if !last_string.is_empty() {
-                        chunk = last_string + &chunk;
-                        last_string = "".into();
-                    }
-
-                    let re = Regex::new(r"data:\s(.*)")?;
-
-                    for captures in re.captures_iter(&chunk) {
-                        if let Some(data_json) = captures.get(1) {
-                            if terminate_response_signal.load(Ordering::Relaxed) {
-                                sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
-                                return Ok(());
-                            }
-
-                            if data_json.as_str() == "[DONE]" {
-                                sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
-                                return Ok(());
-                            }
-
-                            // fix EOF
-                            let answer: Value = match serde_json::from_str(data_json.as_str()) {
-                                Ok(answer) => answer,
-                                Err(_e) => {
-                                    last_string = data_json.as_str().to_string();
-                                    continue;
-                                }
-                            };
-
-                            let msg = answer["choices"][0]["delta"]["content"]
-                                .as_str()
-                                .unwrap_or("\n");
-
-                            if msg != "null" {
-                                sender.send(Event::LLMEvent(LLMAnswer::Answer(msg.to_string())))?;
-                            }
-
-                            sleep(Duration::from_millis(100)).await;
-                        }
-                    }
-                }
             }
-            Err(e) => return Err(Box::new(e)),
+            let turn = sse::stream_openai(res, self.http.read_timeout, &sender).await?;
+
+            let Some(tool_loop) = tool_loop.as_mut() else {
+                break;
+            };
+            if !tool_loop.feed_back(&mut messages, turn, &sender).await? {
+                break;
+            }
         }
+        sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
 
         Ok(())
     }
 }
 
+/// A message of the request, its images as `image_url` content parts after the text.
+fn request_message(message: &Message) -> Result<Value, serde_json::Error> {
+    let mut value = serde_json::to_value(message)?;
+    if !message.attachments.is_empty() {
+        let mut parts = vec![json!({ "type": "text", "text": message.content })];
+        parts.extend(
+            message.attachments.iter().map(
+                |image| json!({ "type": "image_url", "image_url": { "url": image.data_url() } }),
+            ),
+        );
+        value["content"] = json!(parts);
+    }
+    Ok(value)
+}
+
+/// Maps the generation settings onto the OpenAI request body, which has no `top_k`.
+fn apply_generation(body: &mut Value, generation: &GenerationConfig) {
+    set_opt(body, "temperature", &generation.temperature);
+    set_opt(body, "top_p", &generation.top_p);
+    set_opt(body, "max_tokens", &generation.max_tokens);
+    set_opt(body, "stop", &generation.stop);
+    set_opt(body, "seed", &generation.seed);
+    set_opt(body, "presence_penalty", &generation.presence_penalty);
+    set_opt(body, "frequency_penalty", &generation.frequency_penalty);
+}
+
 // for test
 pub async fn call_gpt(messages: Vec<Message>) -> Result<String, Box<dyn std::error::Error + Send>> {
     dotenv().ok();
@@ -200,12 +266,52 @@ pub async fn call_gpt(messages: Vec<Message>) -> Result<String, Box<dyn std::err
 mod tests {
     use super::*;
 
+    use crate::llm::stub::{self, StubResponse};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn compatible_profile_sends_headers_query_and_extra_body() {
+        let stream = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"ok\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let (url, server) = stub::serve_once("text/event-stream", stream).await;
+        let profile = stub::profile_with(
+            "openai_compatible",
+            &format!(
+                r#"
+                url = "{}/v1/chat/completions"
+                model = "Qwen2-7B-Instruct"
+                headers = {{ "X-Gateway-Token" = "secret" }}
+                query = {{ "api-version" = "2024-02-01" }}
+                extra_body = {{ repetition_penalty = 1.1, stream = true }}
+                "#,
+                url
+            ),
+        );
+        let llm = ChatGPT::compatible(&profile, &toml::from_str("").unwrap()).unwrap();
+        let messages = [Message::new(LLMRole::USER, "Hi".to_string())];
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        llm.ask(&messages, sender).await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request
+            .head
+            .starts_with("POST /v1/chat/completions?api-version=2024-02-01 "));
+        assert!(request.head.contains("x-gateway-token: secret"));
+        assert!(!request.head.contains("authorization"));
+        assert_eq!(request.body["model"], "Qwen2-7B-Instruct");
+        assert_eq!(request.body["repetition_penalty"], 1.1);
+        assert_eq!(stub::collect_answer(&mut receiver), "ok");
+    }
+
     #[tokio::test]
     async fn tests_call_to_openai() {
-        let message: Message = Message {
-            role: "user".to_string(),
-            content: "Hi there, this is a test. Give me a short reponse.".to_string(),
-        };
+        let message: Message = Message::new(
+            LLMRole::USER,
+            "Hi there, this is a test. Give me a short reponse.".to_string(),
+        );
 
         let messages: Vec<Message> = vec![message];
 
@@ -213,11 +319,65 @@ mod tests {
         match res {
             Ok(res_str) => {
                 dbg!(res_str);
-                assert!(true);
             }
-            Err(_) => {
-                assert!(false);
+            Err(e) => {
+                panic!("call to openai failed: {}", e);
             }
         }
     }
+
+    #[tokio::test]
+    async fn allowed_tool_call_is_fed_back() {
+        let tool_call = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"read_file\",\"arguments\":\"\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"path\\\":\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"Cargo.toml\\\"}\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let answer = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"It is the manifest.\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let (url, server) = stub::serve(vec![
+            StubResponse::new("200 OK", tool_call).header("Content-Type", "text/event-stream"),
+            StubResponse::new("200 OK", answer).header("Content-Type", "text/event-stream"),
+        ])
+        .await;
+        let profile = stub::profile_with(
+            "openai_compatible",
+            &format!("url = \"{}\"\ntools = true", url),
+        );
+        let config: Config = toml::from_str("file_explorer_path = \".\"").unwrap();
+        let llm = ChatGPT::compatible(&profile, &config).unwrap();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let ask = tokio::spawn(async move {
+            let messages = [Message::new(
+                LLMRole::USER,
+                "What is Cargo.toml?".to_string(),
+            )];
+            llm.ask(&messages, sender).await
+        });
+        loop {
+            if let Event::LLMEvent(LLMAnswer::ToolCall(request)) = receiver.recv().await.unwrap() {
+                assert_eq!(request.call.summary(), "read_file(path: \"Cargo.toml\")");
+                request.answer(true);
+                break;
+            }
+        }
+        ask.await.unwrap().unwrap();
+        assert_eq!(stub::collect_answer(&mut receiver), "It is the manifest.");
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].body["tools"].as_array().unwrap().len() == 4);
+        let messages = requests[1].body["messages"].as_array().unwrap();
+        let (call, result) = (&messages[messages.len() - 2], &messages[messages.len() - 1]);
+        assert_eq!(
+            call["tool_calls"][0]["function"]["arguments"],
+            "{\"path\":\"Cargo.toml\"}"
+        );
+        assert_eq!(result["tool_call_id"], "call_1");
+        assert!(result["content"].as_str().unwrap().starts_with("[package]"));
+    }
 }
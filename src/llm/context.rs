@@ -0,0 +1,212 @@
+//! Keeps requests within the model's context window.
+//!
+//! The preset prompts, pinned messages and the latest message are always
+//! sent; older turns are left out when the conversation no longer fits. A
+//! turn is a user message with the answers that follow it, it is kept or
+//! left out as a whole so no answer is sent without its question.
+
+use super::*;
+
+use std::ops::Range;
+
+use crate::config::ContextStrategy;
+use crate::llm::usage::{context_limit, estimate_tokens, TOKENS_PER_MESSAGE};
+
+/// Tokens kept free for the answer when neither `reserve_tokens` nor `max_tokens` is set.
+const DEFAULT_RESERVE_TOKENS: u64 = 512;
+
+const DEFAULT_WINDOW_MESSAGES: usize = 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContextBudget {
+    /// Context window of the model, `None` when unknown.
+    pub max_context: Option<u64>,
+    /// Tokens kept free for the answer.
+    pub reserve: u64,
+    pub strategy: ContextStrategy,
+    /// Messages kept by the sliding window.
+    pub window: usize,
+}
+
+impl ContextBudget {
+    pub fn new(profile: &Profile, config: &Config) -> Self {
+        let context = profile.context.or(&config.context);
+        let model = profile.model.as_deref().unwrap_or_default();
+
+        Self {
+            max_context: profile.context_window().or_else(|| context_limit(model)),
+            reserve: context
+                .reserve_tokens
+                .or(profile.generation.max_tokens.map(u64::from))
+                .or(config.generation.max_tokens.map(u64::from))
+                .unwrap_or(DEFAULT_RESERVE_TOKENS),
+            strategy: context.context_strategy.unwrap_or_default(),
+            window: context.window_messages.unwrap_or(DEFAULT_WINDOW_MESSAGES),
+        }
+    }
+
+    /// The preset prompts leading `history` followed by as much of the rest
+    /// as fits, telling the UI when messages were left out.
+    pub fn request_messages(
+        &self,
+        history: &[Message],
+        sender: &UnboundedSender<Event>,
+    ) -> Result<Vec<Message>, LLMError> {
+        let prompts = history.iter().take_while(|m| m.preset).count();
+        let (messages, left_out) = self.fit(history[..prompts].to_vec(), &history[prompts..]);
+        if left_out > 0 {
+            sender.send(Event::LLMEvent(LLMAnswer::ContextTrimmed(left_out)))?;
+        }
+        Ok(messages)
+    }
+
+    fn fit(&self, prompts: Vec<Message>, history: &[Message]) -> (Vec<Message>, usize) {
+        let turns = turns(history);
+        let last = turns.len().saturating_sub(1);
+        let pinned = |turn: &Range<usize>| history[turn.clone()].iter().any(|m| m.pinned);
+        let mut keep = vec![true; turns.len()];
+
+        if self.strategy == ContextStrategy::Window {
+            let start = history.len().saturating_sub(self.window.max(1));
+            for (i, turn) in turns.iter().enumerate() {
+                keep[i] = turn.start >= start || pinned(turn);
+            }
+        }
+
+        if let Some(max_context) = self.max_context {
+            let budget = max_context.saturating_sub(self.reserve);
+            let cost = |turn: &Range<usize>| {
+                history[turn.clone()]
+                    .iter()
+                    .map(|m| {
+                        m.tokens.unwrap_or_else(|| estimate_tokens(&m.content)) + TOKENS_PER_MESSAGE
+                    })
+                    .sum::<u64>()
+            };
+            let mut total: u64 = prompts
+                .iter()
+                .map(|m| m.tokens.unwrap_or_else(|| estimate_tokens(&m.content)))
+                .map(|tokens| tokens + TOKENS_PER_MESSAGE)
+                .sum::<u64>()
+                + turns
+                    .iter()
+                    .zip(&keep)
+                    .filter(|(_, kept)| **kept)
+                    .map(|(turn, _)| cost(turn))
+                    .sum::<u64>();
+
+            // Oldest first, never the pinned turns nor the one being answered.
+            for (i, turn) in turns.iter().enumerate() {
+                if total <= budget {
+                    break;
+                }
+                if keep[i] && !pinned(turn) && i != last {
+                    keep[i] = false;
+                    total -= cost(turn);
+                }
+            }
+        }
+
+        // The conversation sent starts with a question
+        for (i, turn) in turns.iter().enumerate() {
+            if !keep[i] {
+                continue;
+            }
+            if history[turn.start].role == LLMRole::USER || i == last {
+                break;
+            }
+            keep[i] = false;
+        }
+
+        let mut messages = prompts;
+        let mut left_out = 0;
+        for (turn, kept) in turns.into_iter().zip(keep) {
+            if kept {
+                messages.extend_from_slice(&history[turn]);
+            } else {
+                left_out += turn.len();
+            }
+        }
+
+        (messages, left_out)
+    }
+}
+
+/// Splits `history` into turns, each starting at a user message. Messages
+/// before the first one form a turn of their own.
+fn turns(history: &[Message]) -> Vec<Range<usize>> {
+    let mut turns: Vec<Range<usize>> = Vec::new();
+    for (i, message) in history.iter().enumerate() {
+        match turns.last_mut() {
+            Some(turn) if message.role != LLMRole::USER => turn.end = i + 1,
+            _ => turns.push(i..i + 1),
+        }
+    }
+    turns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: LLMRole, tokens: u64, pinned: bool) -> Message {
+        let mut message = Message::new(role, format!("{} tokens", tokens));
+        message.tokens = Some(tokens);
+        message.pinned = pinned;
+        message
+    }
+
+    fn budget(strategy: ContextStrategy) -> ContextBudget {
+        ContextBudget {
+            max_context: Some(200),
+            reserve: 50,
+            strategy,
+            window: 2,
+        }
+    }
+
+    #[test]
+    fn truncation_keeps_prompts_pinned_and_latest() {
+        let prompts = vec![message(LLMRole::SYSTEM, 16, false)];
+        let history = vec![
+            message(LLMRole::USER, 36, true),
+            message(LLMRole::ASSISTANT, 36, false),
+            message(LLMRole::USER, 36, false),
+            message(LLMRole::ASSISTANT, 36, false),
+            message(LLMRole::USER, 36, false),
+        ];
+
+        // 20 + 5 * 40 = 220 tokens against a budget of 150: the oldest
+        // unpinned turn goes, question and answer together.
+        let (messages, left_out) = budget(ContextStrategy::Truncate).fit(prompts.clone(), &history);
+        assert_eq!(left_out, 2);
+        let ids: Vec<u64> = messages.iter().skip(1).map(|m| m.id).collect();
+        assert_eq!(ids, vec![history[0].id, history[1].id, history[4].id]);
+
+        // A leading answer without its question is never sent
+        let greeting = [
+            message(LLMRole::ASSISTANT, 6, false),
+            message(LLMRole::USER, 6, false),
+        ];
+        let (messages, left_out) = budget(ContextStrategy::Truncate).fit(prompts, &greeting);
+        assert_eq!(left_out, 1);
+        assert_eq!(messages[1].id, greeting[1].id);
+    }
+
+    #[test]
+    fn sliding_window_keeps_last_messages_and_pinned() {
+        let history = vec![
+            message(LLMRole::USER, 1, true),
+            message(LLMRole::ASSISTANT, 1, false),
+            message(LLMRole::USER, 1, false),
+            message(LLMRole::ASSISTANT, 1, false),
+            message(LLMRole::USER, 1, false),
+        ];
+
+        // The turn cut by the window goes entirely, the pinned one stays whole
+        let (messages, left_out) = budget(ContextStrategy::Window).fit(Vec::new(), &history);
+        assert_eq!(left_out, 2);
+        let ids: Vec<u64> = messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![history[0].id, history[1].id, history[4].id]);
+    }
+}
@@ -0,0 +1,282 @@
+//! Errors of a backend request, and retrying the transient ones.
+
+use super::*;
+
+use std::time::Duration;
+
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+/// Attempts of a request before its error is shown.
+const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound of a wait, even when `Retry-After` asks for more.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Response bodies are cut to this many characters in error messages.
+const MAX_BODY_CHARS: usize = 500;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LLMError {
+    /// The api key is missing or was refused (401, 403).
+    Auth(String),
+    /// 429, with the server's `Retry-After` if any.
+    RateLimited {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+    /// Any other non-success status.
+    Http {
+        status: u16,
+        body: String,
+    },
+    /// Connection refused or reset, DNS failure, interrupted body.
+    Network(String),
+    Timeout,
+    /// The response couldn't be parsed.
+    Decode(String),
+    /// An error event inside an otherwise successful stream.
+    Backend(String),
+    /// Invalid request settings, e.g. a header value.
+    Config(String),
+    /// The conversation holds something the backend can not send, e.g. an image.
+    Unsupported(String),
+    /// Stopped by the user, or the UI went away.
+    Cancelled,
+}
+
+impl std::fmt::Display for LLMError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LLMError::Auth(body) => write!(f, "Authentication failed: {}", body),
+            LLMError::RateLimited { body, .. } => write!(f, "Rate limited: {}", body),
+            LLMError::Http { status, body } => write!(f, "HTTP {}: {}", status, body),
+            LLMError::Network(e) => write!(f, "Network error: {}", e),
+            LLMError::Timeout => write!(f, "The request timed out"),
+            LLMError::Decode(e) => write!(f, "Invalid response: {}", e),
+            LLMError::Backend(e) => write!(f, "Backend error: {}", e),
+            LLMError::Config(e) => write!(f, "Invalid request: {}", e),
+            LLMError::Unsupported(e) => write!(f, "Not supported: {}", e),
+            LLMError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for LLMError {}
+
+/// A failed attempt of a request that is sent again after `delay`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retry {
+    pub error: LLMError,
+    pub delay: Duration,
+    pub attempt: u32,
+    /// Attempts left after the first one.
+    pub retries: u32,
+}
+
+impl std::fmt::Display for Retry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\nRetrying in {:.1}s ({}/{})",
+            self.error,
+            self.delay.as_secs_f32(),
+            self.attempt,
+            self.retries
+        )
+    }
+}
+
+impl LLMError {
+    /// Whether sending the same request again may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            LLMError::RateLimited { .. } | LLMError::Network(_) | LLMError::Timeout => true,
+            LLMError::Http { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Reads the error of a non-success response.
+    pub async fn from_response(res: Response) -> Self {
+        let status = res.status();
+        let retry_after = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body: String = res
+            .text()
+            .await
+            .unwrap_or_default()
+            .trim()
+            .chars()
+            .take(MAX_BODY_CHARS)
+            .collect();
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => LLMError::Auth(body),
+            StatusCode::TOO_MANY_REQUESTS => LLMError::RateLimited { retry_after, body },
+            status => LLMError::Http {
+                status: status.as_u16(),
+                body,
+            },
+        }
+    }
+}
+
+impl From<reqwest::Error> for LLMError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            LLMError::Timeout
+        } else if e.is_decode() {
+            LLMError::Decode(e.to_string())
+        } else if e.is_builder() {
+            LLMError::Config(e.to_string())
+        } else if let Some(status) = e.status() {
+            LLMError::Http {
+                status: status.as_u16(),
+                body: e.to_string(),
+            }
+        } else {
+            LLMError::Network(e.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for LLMError {
+    fn from(e: serde_json::Error) -> Self {
+        LLMError::Decode(e.to_string())
+    }
+}
+
+impl From<std::str::Utf8Error> for LLMError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        LLMError::Decode(e.to_string())
+    }
+}
+
+impl From<reqwest::header::InvalidHeaderValue> for LLMError {
+    fn from(e: reqwest::header::InvalidHeaderValue) -> Self {
+        LLMError::Config(e.to_string())
+    }
+}
+
+impl<T> From<tokio::sync::mpsc::error::SendError<T>> for LLMError {
+    fn from(_: tokio::sync::mpsc::error::SendError<T>) -> Self {
+        LLMError::Cancelled
+    }
+}
+
+/// `Retry-After` as delay-seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let delay = date.signed_duration_since(Local::now()).to_std().ok()?;
+    Some(delay)
+}
+
+/// Sends `request` a single time, for lookups whose failure is tolerated or
+/// that must not keep the UI waiting.
+pub async fn send_once(request: RequestBuilder) -> Result<Response, LLMError> {
+    match request.send().await {
+        Ok(res) if res.status().is_success() => Ok(res),
+        Ok(res) => Err(LLMError::from_response(res).await),
+        Err(e) => Err(LLMError::from(e)),
+    }
+}
+
+/// Sends `request`, retrying rate limits, 5xx answers and connection failures
+/// with exponential backoff. Only the request is retried: once a response
+/// streams, its errors are final.
+pub async fn send(
+    request: RequestBuilder,
+    sender: &UnboundedSender<Event>,
+) -> Result<Response, LLMError> {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1.. {
+        let attempt_request = request
+            .try_clone()
+            .ok_or_else(|| LLMError::Config("the request body can't be resent".to_string()))?;
+
+        let error = match send_once(attempt_request).await {
+            Ok(res) => return Ok(res),
+            Err(error) => error,
+        };
+
+        if !error.is_transient() || attempt >= MAX_ATTEMPTS {
+            return Err(error);
+        }
+
+        let delay = match &error {
+            LLMError::RateLimited {
+                retry_after: Some(retry_after),
+                ..
+            } => *retry_after,
+            _ => backoff,
+        }
+        .min(MAX_BACKOFF);
+        backoff *= 2;
+
+        sender.send(Event::LLMEvent(LLMAnswer::Retrying(Retry {
+            error,
+            delay,
+            attempt,
+            retries: MAX_ATTEMPTS - 1,
+        })))?;
+
+        tokio::time::sleep(delay).await;
+    }
+
+    unreachable!("the retry loop returns on its last attempt")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::{self, StubResponse};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn retry_after_forms() {
+        assert_eq!(parse_retry_after("3"), Some(Duration::from_secs(3)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            None,
+            "a date in the past means no wait"
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_is_retried_and_auth_is_not() {
+        let (url, server) = stub::serve(vec![
+            StubResponse::new("429 Too Many Requests", "slow down").header("Retry-After", "0"),
+            StubResponse::new("503 Service Unavailable", "overloaded"),
+            StubResponse::new("200 OK", "fine"),
+        ])
+        .await;
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        let client = reqwest::Client::new();
+        let res = send(client.post(&url).body("{}"), &sender).await.unwrap();
+        assert_eq!(res.text().await.unwrap(), "fine");
+        assert_eq!(server.await.unwrap().len(), 3);
+        let retries: Vec<Event> = std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        assert_eq!(retries.len(), 2);
+        assert!(matches!(
+            &retries[1],
+            Event::LLMEvent(LLMAnswer::Retrying(Retry { attempt: 2, .. }))
+        ));
+
+        let (url, server) =
+            stub::serve(vec![StubResponse::new("401 Unauthorized", "bad key")]).await;
+        let err = send(client.post(&url).body("{}"), &sender)
+            .await
+            .unwrap_err();
+        assert_eq!(err, LLMError::Auth("bad key".to_string()));
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+}
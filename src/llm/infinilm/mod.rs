@@ -0,0 +1,384 @@
+use super::*;
+
+use std::sync::{Arc, Mutex};
+
+use reqwest::header::CONTENT_TYPE;
+use tracing::warn;
+
+use crate::config::{GenerationConfig, InfiniLMConfig, InfiniLMKeys, Profile};
+use crate::llm::context::ContextBudget;
+use crate::llm::sse::SseDecoder;
+
+pub mod session;
+
+use self::session::{Entry, Plan, Sessions};
+
+#[derive(Clone, Debug)]
+pub struct InfiniLM {
+    http: net::Http,
+    url: String,
+    fork_url: String,
+    drop_url: String,
+    keys: InfiniLMKeys,
+    model: Option<String>,
+    generation: GenerationConfig,
+    context: ContextBudget,
+    /// `None` when the service is used without sessions.
+    sessions: Option<Arc<Mutex<Sessions>>>,
+}
+
+impl InfiniLM {
+    pub fn new(profile: &Profile, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let url = profile
+            .url
+            .clone()
+            .unwrap_or_else(InfiniLMConfig::default_url);
+        let options = &profile.infinilm;
+        let generation = profile.generation.or(&config.generation);
+        if generation.presence_penalty.is_some() || generation.frequency_penalty.is_some() {
+            warn!(
+                "InfiniLM has no presence or frequency penalty, profile `{}` sends neither",
+                profile.name
+            );
+        }
+
+        Ok(Self {
+            http: net::Http::new(profile)?,
+            fork_url: options
+                .fork_url
+                .clone()
+                .unwrap_or_else(|| sibling_url(&url, "fork")),
+            drop_url: options
+                .drop_url
+                .clone()
+                .unwrap_or_else(|| sibling_url(&url, "drop")),
+            url,
+            keys: options.payload_keys.clone(),
+            model: profile.model.clone(),
+            generation,
+            context: ContextBudget::new(profile, config),
+            sessions: options
+                .sessions
+                .unwrap_or(true)
+                .then(|| Arc::new(Mutex::new(Sessions::new()))),
+        })
+    }
+
+    fn infer_body(&self, inputs: &[Message]) -> Value {
+        let mut body = json!({
+            "encoding": "text",
+            "stream": true,
+        });
+        body[&self.keys.inputs] = json!(inputs);
+        // 未配置的参数交给 InfiniLM 服务端的默认值
+        set_opt(
+            &mut body,
+            &self.keys.temperature,
+            &self.generation.temperature,
+        );
+        set_opt(&mut body, &self.keys.top_k, &self.generation.top_k);
+        set_opt(&mut body, &self.keys.top_p, &self.generation.top_p);
+        set_opt(
+            &mut body,
+            &self.keys.max_tokens,
+            &self.generation.max_tokens,
+        );
+        set_opt(&mut body, &self.keys.stop, &self.generation.stop);
+        set_opt(&mut body, &self.keys.seed, &self.generation.seed);
+        body
+    }
+
+    /// Sends the whole conversation, the service keeps nothing.
+    async fn infer(
+        &self,
+        messages: &[Message],
+        sender: &UnboundedSender<Event>,
+    ) -> Result<String, LLMError> {
+        let body = self.infer_body(messages);
+        info!("InfiniLM body data json: {:?} ", body);
+
+        let request = self.http.client.post(&self.url).json(&body);
+        let res = error::send(request, sender).await?;
+        sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
+        stream_infer(res, self.http.read_timeout, sender).await
+    }
+
+    /// Sends what the session of `plan` doesn't hold yet.
+    async fn infer_in_session(
+        &self,
+        plan: &Plan,
+        messages: &[Message],
+        sender: &UnboundedSender<Event>,
+    ) -> Result<String, LLMError> {
+        for session_id in &plan.evicted {
+            self.drop_session(session_id, sender).await;
+        }
+
+        if let Some(fork_from) = &plan.fork_from {
+            let mut body = json!({});
+            body[&self.keys.session_id] = json!(fork_from);
+            body[&self.keys.new_session_id] = json!(plan.session_id);
+            let request = self.http.client.post(&self.fork_url).json(&body);
+            error::send(request, sender).await?;
+        }
+
+        let mut body = self.infer_body(&messages[plan.dialog_pos..]);
+        body[&self.keys.session_id] = json!(plan.session_id);
+        body[&self.keys.dialog_pos] = json!(plan.dialog_pos);
+        info!("InfiniLM body data json: {:?} ", body);
+
+        let request = self.http.client.post(&self.url).json(&body);
+        let res = error::send(request, sender).await?;
+        sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
+        stream_infer(res, self.http.read_timeout, sender).await
+    }
+
+    /// Frees a session on the server, failures only cost its memory until the server drops it.
+    async fn drop_session(&self, session_id: &str, sender: &UnboundedSender<Event>) {
+        let mut body = json!({});
+        body[&self.keys.session_id] = json!(session_id);
+        let request = self.http.client.post(&self.drop_url).json(&body);
+        if let Err(e) = error::send(request, sender).await {
+            info!("InfiniLM drop of session {} failed: {}", session_id, e);
+        }
+    }
+}
+
+#[async_trait]
+impl LLM for InfiniLM {
+    fn backend(&self) -> LLMBackend {
+        LLMBackend::InfiniLM
+    }
+
+    fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or("default")
+    }
+
+    async fn ask(
+        &self,
+        messages: &[Message],
+        sender: UnboundedSender<Event>,
+    ) -> Result<(), LLMError> {
+        let messages = self.context.request_messages(messages, &sender)?;
+
+        let Some(sessions) = &self.sessions else {
+            self.infer(&messages, &sender).await?;
+            sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
+            return Ok(());
+        };
+
+        let mut dialog: Vec<Entry> = messages
+            .iter()
+            .map(|m| (m.role, m.content.clone()))
+            .collect();
+        let plan = sessions.lock().unwrap().plan(&dialog);
+        let mut pending = PendingSession::new(self, sessions, &plan.session_id);
+
+        let answer = match self.infer_in_session(&plan, &messages, &sender).await {
+            // The session may be gone on the server, start over in a new one
+            Err(LLMError::Http { status, .. })
+                if (400..500).contains(&status)
+                    && (plan.dialog_pos > 0 || plan.fork_from.is_some()) =>
+            {
+                let plan = sessions.lock().unwrap().start_over(&plan.session_id);
+                pending.session_id.clone_from(&plan.session_id);
+                self.infer_in_session(&plan, &messages, &sender).await?
+            }
+            res => res?,
+        };
+
+        dialog.push((LLMRole::ASSISTANT, answer));
+        sessions.lock().unwrap().commit(&pending.session_id, dialog);
+        pending.done = true;
+
+        sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
+        Ok(())
+    }
+}
+
+/// Drops the session of an answer that didn't complete, its dialog on the
+/// server is unknown and it may still be generating.
+struct PendingSession {
+    llm: InfiniLM,
+    sessions: Arc<Mutex<Sessions>>,
+    session_id: String,
+    done: bool,
+}
+
+impl PendingSession {
+    fn new(llm: &InfiniLM, sessions: &Arc<Mutex<Sessions>>, session_id: &str) -> Self {
+        Self {
+            llm: llm.clone(),
+            sessions: sessions.clone(),
+            session_id: session_id.to_string(),
+            done: false,
+        }
+    }
+}
+
+impl Drop for PendingSession {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        self.sessions.lock().unwrap().forget(&self.session_id);
+
+        let (llm, session_id) = (self.llm.clone(), std::mem::take(&mut self.session_id));
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+                llm.drop_session(&session_id, &sender).await;
+            });
+        }
+    }
+}
+
+/// `url` with its last path segment replaced, e.g. `/infer` by `/fork`.
+fn sibling_url(url: &str, endpoint: &str) -> String {
+    match url.rsplit_once('/') {
+        Some((base, _)) if base.contains("://") && !base.ends_with('/') => {
+            format!("{}/{}", base, endpoint)
+        }
+        _ => format!("{}/{}", url.trim_end_matches('/'), endpoint),
+    }
+}
+
+/// Forwards the answer to the UI and returns it. Event streams carry the text
+/// in `data:` fields, errors as `event: error` or an `error` field; any other
+/// response is the raw text.
+async fn stream_infer(
+    mut res: reqwest::Response,
+    read_timeout: Option<Duration>,
+    sender: &UnboundedSender<Event>,
+) -> Result<String, LLMError> {
+    let event_stream = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+
+    let mut answer = String::new();
+    let mut forward = |text: String| -> Result<(), LLMError> {
+        if !text.is_empty() {
+            answer.push_str(&text);
+            sender.send(Event::LLMEvent(LLMAnswer::Answer(text)))?;
+        }
+        Ok(())
+    };
+
+    if event_stream {
+        let mut decoder = SseDecoder::new();
+        while let Some(chunk) = net::chunk(&mut res, read_timeout).await? {
+            for event in decoder.feed(&chunk) {
+                if let Some(text) = event_text(event.event.as_deref(), &event.data)? {
+                    forward(text)?;
+                }
+            }
+        }
+        decoder.finish();
+    } else {
+        // Raw text, a UTF-8 sequence may be split across chunks
+        let mut pending = Vec::new();
+        while let Some(chunk) = net::chunk(&mut res, read_timeout).await? {
+            pending.extend_from_slice(&chunk);
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(e) => return Err(e.into()),
+            };
+            let text = String::from_utf8(pending.drain(..valid).collect()).unwrap_or_default();
+            info!("InfiniLM chunk data: {:?} ", text);
+            forward(text)?;
+        }
+        if !pending.is_empty() {
+            forward(String::from_utf8_lossy(&pending).into_owned())?;
+        }
+    }
+
+    Ok(answer)
+}
+
+/// The answer text of one event, `None` for the end marker.
+fn event_text(event: Option<&str>, data: &str) -> Result<Option<String>, LLMError> {
+    if event == Some("error") {
+        return Err(LLMError::Backend(data.to_string()));
+    }
+    if data == "[DONE]" {
+        return Ok(None);
+    }
+
+    match serde_json::from_str::<Value>(data) {
+        Ok(Value::String(text)) => Ok(Some(text)),
+        Ok(Value::Object(object)) => {
+            if let Some(error) = object.get("error") {
+                let error = match error {
+                    Value::String(error) => error.clone(),
+                    error => error.to_string(),
+                };
+                return Err(LLMError::Backend(error));
+            }
+            Ok(["text", "content", "token"]
+                .iter()
+                .find_map(|key| object.get(*key).and_then(Value::as_str))
+                .map(String::from))
+        }
+        _ => Ok(Some(data.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::{self, StubResponse};
+    use tokio::sync::mpsc;
+
+    const FIRST_ANSWER: &str = "data: {\"text\":\"你好\"}\n\ndata: {\"text\":\"!\"}\n\n";
+    const SECOND_ANSWER: &str = "data: {\"text\":\"Fine\"}\n\nevent: error\ndata: session busy\n\n";
+
+    #[tokio::test]
+    async fn later_turns_only_send_new_messages() {
+        let (url, server) = stub::serve(vec![
+            StubResponse::new("200 OK", FIRST_ANSWER).header("Content-Type", "text/event-stream"),
+            StubResponse::new("200 OK", SECOND_ANSWER).header("Content-Type", "text/event-stream"),
+        ])
+        .await;
+        let profile = stub::profile_with(
+            "infinilm",
+            &format!(
+                "url = \"{}/infer\"\nmax_tokens = 64\nstop = [\"</s>\"]\nseed = 7",
+                url
+            ),
+        );
+        let llm = InfiniLM::new(&profile, &toml::from_str("").unwrap()).unwrap();
+        assert_eq!(llm.fork_url, format!("{}/fork", url));
+
+        let mut messages = vec![Message::new(LLMRole::USER, "Hi".to_string())];
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        llm.ask(&messages, sender.clone()).await.unwrap();
+        assert_eq!(stub::collect_answer(&mut receiver), "你好!");
+
+        messages.push(Message::new(LLMRole::ASSISTANT, "你好!".to_string()));
+        messages.push(Message::new(LLMRole::USER, "How are you?".to_string()));
+        let err = llm.ask(&messages, sender).await.unwrap_err();
+        assert_eq!(err, LLMError::Backend("session busy".to_string()));
+        assert_eq!(stub::collect_answer(&mut receiver), "Fine");
+
+        let requests = server.await.unwrap();
+        let (first, second) = (&requests[0].body, &requests[1].body);
+        assert_eq!(first["dialog_pos"], 0);
+        assert_eq!(
+            (&first["max_tokens"], &first["stop"][0], &first["seed"]),
+            (&json!(64), &json!("</s>"), &json!(7))
+        );
+        assert_eq!(second["session_id"], first["session_id"]);
+        assert_eq!(
+            second["dialog_pos"],
+            first["inputs"].as_array().unwrap().len() + 1
+        );
+        assert_eq!(
+            second["inputs"],
+            json!([{ "role": "user", "content": "How are you?" }])
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Bookkeeping of the sessions kept on the InfiniLM service.
+//!
+//! The service holds the KV cache of a session's dialog. A request names the
+//! session and a `dialog_pos`: the server keeps that many messages of the
+//! dialog and appends the new inputs, so only what changed is sent. A
+//! conversation that diverges from a session, e.g. a new chat sharing the
+//! default prompts, forks it, and the original stays usable for its own
+//! conversation.
+
+use super::*;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sessions kept on the server, the least recently used ones are dropped.
+const MAX_SESSIONS: usize = 4;
+
+/// One message of a session's dialog.
+pub type Entry = (LLMRole, String);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Session {
+    id: String,
+    dialog: Vec<Entry>,
+    used: u64,
+}
+
+/// How a request uses the sessions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+    pub session_id: String,
+    /// Forks this session into `session_id` before the request.
+    pub fork_from: Option<String>,
+    /// Messages of the session's dialog the server keeps.
+    pub dialog_pos: usize,
+    /// Sessions to drop on the server, to stay within `MAX_SESSIONS`.
+    pub evicted: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: Vec<Session>,
+    clock: u64,
+    created: u64,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Picks the session sharing the longest start with `dialog`, which is
+    /// everything the request sends. Its last message is always sent again.
+    pub fn plan(&mut self, dialog: &[Entry]) -> Plan {
+        let limit = dialog.len().saturating_sub(1);
+        let best = self
+            .sessions
+            .iter()
+            .map(|session| (common_prefix(&session.dialog, dialog).min(limit), session))
+            .filter(|(common, _)| *common > 0)
+            .max_by_key(|(common, session)| (*common, session.used))
+            .map(|(common, session)| (common, session.id.clone(), session.dialog.len()));
+
+        let (session_id, fork_from, dialog_pos) = match best {
+            Some((common, id, len)) if common == len => (id, None, common),
+            Some((common, id, _)) => (self.new_id(), Some(id), common),
+            None => (self.new_id(), None, 0),
+        };
+
+        let evicted = self.evict(&session_id, fork_from.as_deref());
+        Plan {
+            session_id,
+            fork_from,
+            dialog_pos,
+            evicted,
+        }
+    }
+
+    /// A new session for the whole dialog, when `session_id` was refused.
+    pub fn start_over(&mut self, session_id: &str) -> Plan {
+        self.forget(session_id);
+        let session_id = self.new_id();
+        Plan {
+            evicted: self.evict(&session_id, None),
+            session_id,
+            fork_from: None,
+            dialog_pos: 0,
+        }
+    }
+
+    /// Records the dialog the server holds for `session_id` after an answer.
+    pub fn commit(&mut self, session_id: &str, dialog: Vec<Entry>) {
+        self.clock += 1;
+        let used = self.clock;
+        match self.sessions.iter_mut().find(|s| s.id == session_id) {
+            Some(session) => {
+                session.dialog = dialog;
+                session.used = used;
+            }
+            None => self.sessions.push(Session {
+                id: session_id.to_string(),
+                dialog,
+                used,
+            }),
+        }
+    }
+
+    /// Forgets a session whose dialog on the server is unknown.
+    pub fn forget(&mut self, session_id: &str) {
+        self.sessions.retain(|s| s.id != session_id);
+    }
+
+    fn new_id(&mut self) -> String {
+        self.created += 1;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        format!("infini-{:x}-{}", now, self.created)
+    }
+
+    /// Frees a slot for `session_id` when it is new, keeping `fork_from`.
+    fn evict(&mut self, session_id: &str, fork_from: Option<&str>) -> Vec<String> {
+        let mut evicted = Vec::new();
+        if self.sessions.iter().any(|s| s.id == session_id) {
+            return evicted;
+        }
+        while self.sessions.len() >= MAX_SESSIONS {
+            let Some(oldest) = self
+                .sessions
+                .iter()
+                .filter(|s| Some(s.id.as_str()) != fork_from)
+                .min_by_key(|s| s.used)
+                .map(|s| s.id.clone())
+            else {
+                break;
+            };
+            self.forget(&oldest);
+            evicted.push(oldest);
+        }
+        evicted
+    }
+}
+
+fn common_prefix(a: &[Entry], b: &[Entry]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dialog(contents: &[&str]) -> Vec<Entry> {
+        contents
+            .iter()
+            .enumerate()
+            .map(|(i, content)| {
+                let role = if i % 2 == 0 {
+                    LLMRole::USER
+                } else {
+                    LLMRole::ASSISTANT
+                };
+                (role, content.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sessions_are_extended_forked_and_evicted() {
+        let mut sessions = Sessions::new();
+
+        let first = sessions.plan(&dialog(&["hi"]));
+        assert_eq!((first.fork_from.as_ref(), first.dialog_pos), (None, 0));
+        sessions.commit(&first.session_id, dialog(&["hi", "hello"]));
+
+        // The next turn only sends the new message
+        let next = sessions.plan(&dialog(&["hi", "hello", "how are you?"]));
+        assert_eq!(next.session_id, first.session_id);
+        assert_eq!((next.fork_from.as_ref(), next.dialog_pos), (None, 2));
+        sessions.commit(
+            &next.session_id,
+            dialog(&["hi", "hello", "how are you?", "fine"]),
+        );
+
+        // Another conversation with the same start forks the session
+        let fork = sessions.plan(&dialog(&["hi", "hello", "bye"]));
+        assert_eq!(fork.fork_from, Some(first.session_id.clone()));
+        assert_eq!(fork.dialog_pos, 2);
+        sessions.commit(&fork.session_id, dialog(&["hi", "hello", "bye", "bye"]));
+
+        // Unrelated conversations start new sessions, the oldest one is dropped
+        for topic in ["a", "b"] {
+            let plan = sessions.plan(&dialog(&[topic]));
+            assert!(plan.evicted.is_empty());
+            sessions.commit(&plan.session_id, dialog(&[topic, "ok"]));
+        }
+        let plan = sessions.plan(&dialog(&["c"]));
+        assert_eq!(plan.evicted, vec![first.session_id]);
+    }
+}
@@ -0,0 +1,242 @@
+use super::*;
+
+use self::context::ContextBudget;
+use self::sse::SseDecoder;
+use crate::config::{GenerationConfig, LlamaCppConfig, LlamaCppEndpoint, LlamaCppOptions, Profile};
+
+#[derive(Clone, Debug)]
+pub struct LlamaCpp {
+    http: net::Http,
+    url: String,
+    model: Option<String>,
+    generation: GenerationConfig,
+    context: ContextBudget,
+    options: LlamaCppOptions,
+}
+
+impl LlamaCpp {
+    pub fn new(profile: &Profile, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            http: net::Http::new(profile)?,
+            url: profile
+                .url
+                .clone()
+                .unwrap_or_else(LlamaCppConfig::default_url),
+            model: profile.model.clone(),
+            generation: profile.generation.or(&config.generation),
+            context: ContextBudget::new(profile, config),
+            options: profile.llamacpp.clone(),
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        let path = match self.options.endpoint {
+            LlamaCppEndpoint::Chat => "/v1/chat/completions",
+            LlamaCppEndpoint::Completion => "/completion",
+        };
+        format!("{}{}", self.url.trim_end_matches('/'), path)
+    }
+
+    fn request_body(&self, messages: &[Message]) -> Value {
+        let mut body = match self.options.endpoint {
+            LlamaCppEndpoint::Chat => json!({ "messages": messages }),
+            LlamaCppEndpoint::Completion => json!({ "prompt": completion_prompt(messages) }),
+        };
+        body["stream"] = json!(true);
+        set_opt(&mut body, "model", &self.model);
+
+        let generation = &self.generation;
+        set_opt(&mut body, "temperature", &generation.temperature);
+        set_opt(&mut body, "top_p", &generation.top_p);
+        set_opt(&mut body, "top_k", &generation.top_k);
+        set_opt(&mut body, "seed", &generation.seed);
+        set_opt(&mut body, "presence_penalty", &generation.presence_penalty);
+        set_opt(
+            &mut body,
+            "frequency_penalty",
+            &generation.frequency_penalty,
+        );
+        match self.options.endpoint {
+            LlamaCppEndpoint::Chat => set_opt(&mut body, "stop", &generation.stop),
+            // Without a chat template the model would carry on with the next user turn.
+            LlamaCppEndpoint::Completion => {
+                let stop = generation
+                    .stop
+                    .clone()
+                    .unwrap_or_else(|| vec!["\nUser:".to_string()]);
+                body["stop"] = json!(stop);
+            }
+        }
+
+        let options = &self.options;
+        let n_predict = options
+            .n_predict
+            .or(generation.max_tokens.map(|max| max as i32));
+        set_opt(&mut body, "n_predict", &n_predict);
+        set_opt(&mut body, "cache_prompt", &options.cache_prompt);
+        set_opt(&mut body, "grammar", &options.grammar);
+        set_opt(&mut body, "json_schema", &options.json_schema);
+        set_opt(&mut body, "id_slot", &options.slot_id);
+
+        body
+    }
+}
+
+#[async_trait]
+impl LLM for LlamaCpp {
+    fn backend(&self) -> LLMBackend {
+        LLMBackend::LlamaCpp
+    }
+
+    fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or("default")
+    }
+
+    async fn ask(
+        &self,
+        messages: &[Message],
+        sender: UnboundedSender<Event>,
+    ) -> Result<(), LLMError> {
+        let body = self.request_body(&self.context.request_messages(messages, &sender)?);
+
+        let request = self.http.client.post(self.endpoint()).json(&body);
+
+        let mut res = error::send(request, &sender).await?;
+        let mut decoder = SseDecoder::new();
+
+        sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
+        'stream: while let Some(chunk) = net::chunk(&mut res, self.http.read_timeout).await? {
+            for event in decoder.feed(&chunk) {
+                let chunk = Chunk::parse(&event.data)?;
+                if let Some(content) = chunk.content {
+                    sender.send(Event::LLMEvent(LLMAnswer::Answer(content)))?;
+                }
+                if let Some(timings) = chunk.timings {
+                    let usage = Usage::reported(timings.prompt_tokens, timings.predicted_tokens);
+                    sender.send(Event::LLMEvent(LLMAnswer::Usage(usage)))?;
+                    sender.send(Event::LLMEvent(LLMAnswer::Timings(timings)))?;
+                }
+                if chunk.stop {
+                    break 'stream;
+                }
+            }
+        }
+        sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
+
+        Ok(())
+    }
+}
+
+/// Renders the conversation for the raw `/completion` endpoint.
+fn completion_prompt(messages: &[Message]) -> String {
+    let mut prompt = String::new();
+    for message in messages {
+        match message.role {
+            LLMRole::SYSTEM => prompt.push_str(&format!("{}\n\n", message.content)),
+            LLMRole::USER => prompt.push_str(&format!("User: {}\n", message.content)),
+            LLMRole::ASSISTANT => prompt.push_str(&format!("Assistant: {}\n", message.content)),
+        }
+    }
+    prompt.push_str("Assistant:");
+    prompt
+}
+
+/// One streamed event of either endpoint.
+#[derive(Debug, Default, PartialEq)]
+struct Chunk {
+    content: Option<String>,
+    timings: Option<Timings>,
+    stop: bool,
+}
+
+impl Chunk {
+    fn parse(data: &str) -> Result<Self, serde_json::Error> {
+        if data.trim() == "[DONE]" {
+            return Ok(Chunk {
+                stop: true,
+                ..Default::default()
+            });
+        }
+
+        let data: Value = serde_json::from_str(data)?;
+        let choice = &data["choices"][0];
+        let content = choice["delta"]["content"]
+            .as_str()
+            .or(data["content"].as_str())
+            .filter(|content| !content.is_empty())
+            .map(String::from);
+        let timings = data.get("timings").map(|timings| Timings {
+            prompt_tokens: timings["prompt_n"].as_u64().unwrap_or_default(),
+            prompt_per_second: timings["prompt_per_second"].as_f64().unwrap_or_default(),
+            predicted_tokens: timings["predicted_n"].as_u64().unwrap_or_default(),
+            predicted_per_second: timings["predicted_per_second"].as_f64().unwrap_or_default(),
+        });
+        let stop = data["stop"].as_bool().unwrap_or(false) || !choice["finish_reason"].is_null();
+
+        Ok(Chunk {
+            content,
+            timings,
+            stop,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion_and_chat_chunks_carry_timings() {
+        let last = Chunk::parse(
+            r#"{"content":"","stop":true,"timings":{"prompt_n":12,"prompt_ms":80.5,"prompt_per_second":149.1,"predicted_n":64,"predicted_ms":1600.0,"predicted_per_second":40.0}}"#,
+        )
+        .unwrap();
+        assert!(last.stop);
+        assert_eq!(last.content, None);
+        assert_eq!(
+            last.timings,
+            Some(Timings {
+                prompt_tokens: 12,
+                prompt_per_second: 149.1,
+                predicted_tokens: 64,
+                predicted_per_second: 40.0,
+            })
+        );
+
+        let chat = Chunk::parse(
+            r#"{"choices":[{"index":0,"delta":{"content":"你好"},"finish_reason":null}],"object":"chat.completion.chunk"}"#,
+        )
+        .unwrap();
+        assert_eq!(chat.content.as_deref(), Some("你好"));
+        assert!(!chat.stop);
+
+        assert!(Chunk::parse("[DONE]").unwrap().stop);
+    }
+
+    #[test]
+    fn completion_body_uses_llamacpp_options() {
+        let profile = stub::profile_with(
+            "llamacpp",
+            r#"
+            endpoint = "completion"
+            max_tokens = 256
+            cache_prompt = true
+            slot_id = 1
+            json_schema = { type = "object" }
+            "#,
+        );
+        let llm = LlamaCpp::new(&profile, &toml::from_str("").unwrap()).unwrap();
+        let body = llm.request_body(&[
+            Message::new(LLMRole::SYSTEM, "Be brief.".to_string()),
+            Message::new(LLMRole::USER, "Hi".to_string()),
+        ]);
+
+        assert_eq!(llm.endpoint(), "http://localhost:8080/completion");
+        assert_eq!(body["prompt"], "Be brief.\n\nUser: Hi\nAssistant:");
+        assert_eq!(body["n_predict"], 256);
+        assert_eq!(body["cache_prompt"], true);
+        assert_eq!(body["id_slot"], 1);
+        assert_eq!(body["json_schema"], json!({ "type": "object" }));
+        assert_eq!(body["stop"], json!(["\nUser:"]));
+    }
+}
@@ -0,0 +1,202 @@
+use super::*;
+
+use std::time::Duration;
+
+use crate::config::{MockMode, MockOptions, Profile};
+
+const MARKDOWN: &str = r#"# Mock answer
+
+This answer comes from the **mock** backend, no model server is involved.
+
+- Streams in small chunks
+- Exercises *markdown* rendering
+- Ends with a code block
+
+```rust
+fn main() {
+    let greeting = "你好, InfiniTUI";
+    println!("{}", greeting);
+}
+```
+
+> Set `mode = "fixture"` to stream a file instead.
+"#;
+
+/// Offline backend streaming a deterministic answer, for demos and UI work.
+#[derive(Clone, Debug)]
+pub struct Mock {
+    options: MockOptions,
+}
+
+impl Mock {
+    pub fn new(profile: &Profile) -> Self {
+        Self {
+            options: profile.mock.clone(),
+        }
+    }
+
+    fn response(&self, messages: &[Message]) -> Result<String, LLMError> {
+        Ok(match self.options.mode {
+            MockMode::Echo => messages
+                .iter()
+                .rev()
+                .find(|m| m.role == LLMRole::USER)
+                .map(|m| m.content.clone())
+                .unwrap_or_default(),
+            MockMode::Markdown => MARKDOWN.to_string(),
+            MockMode::Fixture => {
+                let path = self.options.fixture.as_ref().ok_or_else(|| {
+                    LLMError::Config(
+                        "the mock backend needs a `fixture` file in fixture mode".to_string(),
+                    )
+                })?;
+                fs::read_to_string(path).map_err(|e| {
+                    LLMError::Config(format!("can not read fixture {:?}: {}", path, e))
+                })?
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl LLM for Mock {
+    fn backend(&self) -> LLMBackend {
+        LLMBackend::Mock
+    }
+
+    fn model(&self) -> &str {
+        match self.options.mode {
+            MockMode::Echo => "echo",
+            MockMode::Markdown => "markdown",
+            MockMode::Fixture => "fixture",
+        }
+    }
+
+    async fn ask(
+        &self,
+        messages: &[Message],
+        sender: UnboundedSender<Event>,
+    ) -> Result<(), LLMError> {
+        let response = self.response(messages)?;
+        let chars: Vec<char> = response.chars().collect();
+        let delay = Duration::from_millis(self.options.delay_ms);
+
+        sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
+        for (i, chunk) in chars.chunks(self.options.chunk_size.max(1)).enumerate() {
+            if self.options.fail_after == Some(i) {
+                return Err(LLMError::Network(format!(
+                    "mock error injected after {} chunks",
+                    i
+                )));
+            }
+
+            sender.send(Event::LLMEvent(LLMAnswer::Answer(chunk.iter().collect())))?;
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub;
+    use crate::ui::{Chat, Formatter};
+    use tokio::sync::mpsc;
+
+    fn mock(options: &str) -> Mock {
+        Mock::new(&stub::profile_with("mock", options))
+    }
+
+    #[tokio::test]
+    async fn echo_streams_into_the_chat() {
+        let llm = mock("chunk_size = 3\ndelay_ms = 0");
+        let messages = [Message::new(LLMRole::USER, "你好, mock!".to_string())];
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        llm.ask(&messages, sender).await.unwrap();
+
+        let (config, assets) = Formatter::init();
+        let formatter = Formatter::new(&config, &assets);
+        let mut chat = Chat::default();
+        let mut chunks = 0;
+        while let Ok(Event::LLMEvent(event)) = receiver.try_recv() {
+            if let LLMAnswer::Answer(_) = event {
+                chunks += 1;
+            }
+            chat.handle_answer(event, &formatter);
+        }
+
+        assert_eq!(chunks, 3);
+        assert_eq!(chat.messages.last().unwrap().content, "你好, mock!");
+    }
+
+    #[tokio::test]
+    async fn injected_error_interrupts_the_stream() {
+        let llm = mock("mode = \"markdown\"\nchunk_size = 10\ndelay_ms = 0\nfail_after = 2");
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let err = llm.ask(&[], sender).await.unwrap_err();
+
+        assert_eq!(
+            err,
+            LLMError::Network("mock error injected after 2 chunks".to_string())
+        );
+        assert_eq!(stub::collect_answer(&mut receiver), &MARKDOWN[..20]);
+    }
+
+    #[tokio::test]
+    async fn cancelling_drops_a_stalled_answer() {
+        let llm = mock("mode = \"markdown\"\ndelay_ms = 60000");
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let cancel = CancellationToken::new();
+
+        let stop = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            stop.cancel();
+        });
+        let started = std::time::Instant::now();
+        let err = ask_until_cancelled(&llm, &[], sender, cancel)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err, LLMError::Cancelled);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Event::LLMEvent(LLMAnswer::StartAnswer))
+        ));
+    }
+
+    #[tokio::test]
+    async fn images_are_refused() {
+        let llm = mock("");
+        let mut message = Message::new(LLMRole::USER, "What is this?".to_string());
+        message.attachments.push(Attachment {
+            name: "cat.png".to_string(),
+            path: PathBuf::from("cat.png"),
+            mime: "image/png",
+            size: 4,
+            data: "AAAA".into(),
+        });
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let err = ask_until_cancelled(&llm, &[message], sender, CancellationToken::new())
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            LLMError::Unsupported(
+                "the mock backend can not read images, `cat.png` was not sent".to_string()
+            )
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
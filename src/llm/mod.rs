@@ -1,116 +1,277 @@
-use crate::config::Config;
+use crate::config::{toml_error, Config, Profile};
 use crate::event::Event;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::fs;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
 use strum_macros::Display;
 use strum_macros::EnumIter;
+use strum_macros::EnumString;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
 
-use dirs;
 use dotenv::dotenv;
 use reqwest::header::HeaderMap;
 use serde_json::{json, Value};
 use std;
-use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::info;
 
+pub mod anthropic;
+pub mod attachment;
 pub mod chatglm;
 pub mod chatgpt;
+pub mod context;
+pub mod error;
 pub mod infinilm;
+pub mod llamacpp;
+pub mod mock;
+pub mod net;
 pub mod ollama;
+pub mod preset;
+pub mod sse;
+#[cfg(test)]
+pub mod stub;
+pub mod tools;
+pub mod usage;
 
+use self::anthropic::Anthropic;
+pub use self::attachment::Attachment;
 use self::chatglm::ChatGLM;
 use self::chatgpt::ChatGPT;
+pub use self::error::{LLMError, Retry};
 use self::infinilm::InfiniLM;
+use self::llamacpp::LlamaCpp;
+use self::mock::Mock;
 use self::ollama::Ollama;
+pub use self::tools::ToolRequest;
+pub use self::usage::Usage;
 
 use std::fmt::Debug;
-use std::sync::Arc;
 
 #[async_trait]
 pub trait LLM: Send + Sync + Debug {
+    /// Answers the conversation `messages`, led by the prompts of its preset.
     async fn ask(
         &self,
+        messages: &[Message],
         sender: UnboundedSender<Event>,
-        terminate_response_signal: Arc<AtomicBool>,
-    ) -> Result<(), Box<dyn std::error::Error>>;
+    ) -> Result<(), LLMError>;
 
-    fn append_chat_msg(&mut self, msg: String, role: LLMRole);
-    fn clear(&mut self);
+    fn backend(&self) -> LLMBackend;
+    fn model(&self) -> &str;
+
+    /// Whether image attachments are sent to the model, other backends refuse them.
+    fn supports_images(&self) -> bool {
+        false
+    }
+
+    /// The backend and model, as shown in the title bar.
+    fn name(&self) -> String {
+        format!("{} · {}", self.backend(), self.model())
+    }
+}
+
+/// Runs `llm.ask` until it ends or `cancel` fires. Cancelling drops the
+/// request, so even a stalled server is let go of right away.
+///
+/// A backend without image support refuses a new prompt with images, those of
+/// earlier messages are left out of the request.
+pub async fn ask_until_cancelled(
+    llm: &dyn LLM,
+    messages: &[Message],
+    sender: UnboundedSender<Event>,
+    cancel: CancellationToken,
+) -> Result<(), LLMError> {
+    let mut messages = Cow::Borrowed(messages);
+    if !llm.supports_images() {
+        let prompt = messages.iter().rposition(|m| m.role == LLMRole::USER);
+        if let Some(image) = prompt.and_then(|i| messages[i].attachments.first()) {
+            return Err(LLMError::Unsupported(format!(
+                "the {} backend can not read images, `{}` was not sent",
+                llm.backend(),
+                image.name
+            )));
+        }
+
+        let left_out: usize = messages.iter().map(|m| m.attachments.len()).sum();
+        if left_out > 0 {
+            for message in messages.to_mut() {
+                message.attachments.clear();
+            }
+            sender.send(Event::LLMEvent(LLMAnswer::ImagesLeftOut(left_out)))?;
+        }
+    }
+
+    tokio::select! {
+        res = llm.ask(&messages, sender) => res,
+        _ = cancel.cancelled() => Err(LLMError::Cancelled),
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum LLMAnswer {
     StartAnswer,
     Answer(String),
+    /// Server-side speed of the answer, sent before `EndAnswer` by backends that report it.
+    Timings(Timings),
+    /// Token counts reported by the backend, sent before `EndAnswer`.
+    Usage(Usage),
+    /// Number of older messages left out of the request to fit the context window.
+    ContextTrimmed(usize),
+    /// Estimated tokens of the whole request, sent before it goes out.
+    PromptEstimate(u64),
     EndAnswer,
+    /// Ends the answer when the request failed, after any partial answer.
+    Error(LLMError),
+    /// The model wants to run a tool, which waits for the user's decision.
+    ToolCall(ToolRequest),
+    /// The request failed and is sent again, the answer hasn't started.
+    Retrying(Retry),
+    /// Number of images of earlier messages left out, the backend can't read them.
+    ImagesLeftOut(usize),
+}
+
+/// Prompt processing and generation speed of one answer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timings {
+    pub prompt_tokens: u64,
+    pub prompt_per_second: f64,
+    pub predicted_tokens: u64,
+    pub predicted_per_second: f64,
+}
+
+impl std::fmt::Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "prompt {} tokens, {:.1} t/s · predicted {} tokens, {:.1} t/s",
+            self.prompt_tokens,
+            self.prompt_per_second,
+            self.predicted_tokens,
+            self.predicted_per_second
+        )
+    }
 }
 
-#[derive(EnumIter, Display, Debug)]
+#[derive(EnumIter, Display, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum LLMRole {
     ASSISTANT,
     SYSTEM,
     USER,
 }
 
-#[derive(Deserialize, PartialEq, Debug)]
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone, Copy, EnumIter, EnumString, Display)]
 #[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
 pub enum LLMBackend {
     ChatGPT,
     Ollama,
     ChatGLM,
     InfiniLM,
+    Anthropic,
+    LlamaCpp,
+    #[serde(rename = "openai_compatible")]
+    #[strum(serialize = "openai_compatible")]
+    OpenAICompatible,
+    Mock,
+}
+
+impl LLMBackend {
+    /// Served on this machine or the local network, reached without a proxy.
+    pub fn is_local(&self) -> bool {
+        matches!(
+            self,
+            LLMBackend::InfiniLM | LLMBackend::LlamaCpp | LLMBackend::Ollama
+        )
+    }
 }
 
 pub struct LLMModel;
 
 impl LLMModel {
-    pub async fn init(model: &LLMBackend, config: Arc<Config>) -> Box<dyn LLM> {
-        match model {
-            LLMBackend::ChatGPT => Box::new(ChatGPT::new(config.chatgpt.clone())),
-            LLMBackend::Ollama => Box::new(Ollama::new(config.ollama.clone().unwrap())),
-            LLMBackend::ChatGLM => Box::new(ChatGLM::new(config.chatglm.clone())),
-            LLMBackend::InfiniLM => Box::new(InfiniLM::new(config.infinilm.clone().unwrap())),
-        }
+    pub async fn init(
+        profile: &Profile,
+        config: &Config,
+    ) -> Result<Box<dyn LLM>, Box<dyn std::error::Error>> {
+        Ok(match profile.backend {
+            LLMBackend::ChatGPT => Box::new(ChatGPT::new(profile, config)?),
+            LLMBackend::Ollama => Box::new(Ollama::discover(profile, config).await?),
+            LLMBackend::ChatGLM => Box::new(ChatGLM::new(profile, config)?),
+            LLMBackend::InfiniLM => Box::new(InfiniLM::new(profile, config)?),
+            LLMBackend::Anthropic => Box::new(Anthropic::new(profile, config)?),
+            LLMBackend::LlamaCpp => Box::new(LlamaCpp::new(profile, config)?),
+            LLMBackend::OpenAICompatible => Box::new(ChatGPT::compatible(profile, config)?),
+            LLMBackend::Mock => Box::new(Mock::new(profile)),
+        })
     }
 }
 
-pub fn read_default_prompts() -> Vec<HashMap<String, String>> {
-    let conf_dir = match env::var("CONFIG_DIR") {
-        Ok(dir) => {
-            info!("Using custom config directory: {}", dir);
-            PathBuf::from(dir)
-        }
-        Err(env::VarError::NotPresent) => {
-            let default_dir = dirs::config_dir().unwrap().join("infini");
-            info!("Using default config directory: {:?}", default_dir);
-            default_dir
-        }
-        Err(e) => {
-            panic!("Failed to read CONFIG_DIR: {}", e);
-        }
-    };
-
-    // 构造完整的配置文件路径
-    let prompts_path = conf_dir.join("prompt.toml");
-
-    if !prompts_path.exists() {
-        info!("'prompts.toml' not found in the current config directory.");
+/// Sets `key` on a JSON object when `value` is present.
+fn set_opt<T: Serialize>(object: &mut Value, key: &str, value: &Option<T>) {
+    if let Some(value) = value {
+        object[key] = json!(value);
     }
-
-    read_messages_from_toml(&prompts_path.to_str().unwrap())
 }
 
-#[derive(Debug, Serialize, Clone, Deserialize)]
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// One turn of a conversation.
+///
+/// Serializes to the `{"role", "content"}` shape understood by every backend,
+/// the bookkeeping fields stay on the client side.
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq)]
 pub struct Message {
-    pub role: String,
+    #[serde(skip, default = "Message::next_id")]
+    pub id: u64,
+    pub role: LLMRole,
     pub content: String,
+    #[serde(skip, default = "Local::now")]
+    pub timestamp: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip)]
+    pub attachments: Vec<Attachment>,
+    /// Tokens of `content`, reported by the backend or estimated.
+    #[serde(skip)]
+    pub tokens: Option<u64>,
+    /// Always sent, even when older messages are left out of the request.
+    #[serde(skip)]
+    pub pinned: bool,
+    /// One of the prompts of the conversation's preset, which lead a request.
+    #[serde(skip)]
+    pub preset: bool,
+    /// The answer was stopped before the backend finished it.
+    #[serde(skip)]
+    pub stopped: bool,
+}
+
+impl Message {
+    pub fn new(role: LLMRole, content: String) -> Self {
+        Self {
+            id: Self::next_id(),
+            role,
+            content,
+            timestamp: Local::now(),
+            name: None,
+            attachments: Vec::new(),
+            tokens: None,
+            pinned: false,
+            preset: false,
+            stopped: false,
+        }
+    }
+
+    fn next_id() -> u64 {
+        NEXT_MESSAGE_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    }
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -140,30 +301,11 @@ pub struct PromptsConfig {
     messages: Vec<Message>,
 }
 
-pub fn read_messages_from_toml(file_path: &str) -> Vec<HashMap<String, String>> {
-    let contents = fs::read_to_string(file_path).unwrap_or_default();
-
-    let config: PromptsConfig = toml::from_str(&contents).unwrap_or_else(|_| PromptsConfig {
-        messages: vec![
-            Message {
-                role: "system".to_string(),
-                content: "你是一个 ai 助手，为用户解决问题".to_string(),
-            },
-            Message {
-                role: "assistant".to_string(),
-                content: "请一步步思考".to_string(),
-            },
-        ],
-    });
-
-    config
-        .messages
-        .iter()
-        .map(|msg| {
-            let mut map = HashMap::new();
-            map.insert("role".to_string(), msg.role.clone());
-            map.insert("content".to_string(), msg.content.clone());
-            map
-        })
-        .collect()
+/// The `[[messages]]` of a prompts file.
+pub fn read_messages_from_toml(file_path: &str) -> Result<Vec<Message>, String> {
+    let contents = fs::read_to_string(file_path).map_err(|e| format!("{}: {}", file_path, e))?;
+    let config: PromptsConfig =
+        toml::from_str(&contents).map_err(|e| toml_error(Path::new(file_path), &contents, &e))?;
+
+    Ok(config.messages)
 }
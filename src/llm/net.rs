@@ -0,0 +1,162 @@
+//! HTTP clients of the backends, built once from the profile's `network` table.
+
+use super::*;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use reqwest::{Certificate, Proxy, Response};
+use std::net::IpAddr;
+
+use crate::config::HttpVersion;
+
+/// What the backends take from the profile's `network` table.
+#[derive(Clone, Debug)]
+pub struct Http {
+    pub client: reqwest::Client,
+    /// Gives up a stream the server stopped sending, see [`chunk`].
+    pub read_timeout: Option<Duration>,
+}
+
+impl Http {
+    pub fn new(profile: &Profile) -> Result<Self, LLMError> {
+        Ok(Self {
+            client: client(profile)?,
+            read_timeout: profile.network.read_timeout(),
+        })
+    }
+}
+
+/// The client of `profile`. Without a `proxy` setting the local backends and
+/// loopback urls connect directly, the others follow the environment.
+pub fn client(profile: &Profile) -> Result<reqwest::Client, LLMError> {
+    let network = &profile.network;
+    let mut builder = reqwest::Client::builder();
+
+    builder = match network.proxy.as_deref() {
+        Some("none") => builder.no_proxy(),
+        Some(url) => builder.proxy(
+            Proxy::all(url).map_err(|e| LLMError::Config(format!("proxy `{}`: {}", url, e)))?,
+        ),
+        // 本地模型不需要走代理
+        None if profile.backend.is_local() || is_loopback(profile.url.as_deref()) => {
+            builder.no_proxy()
+        }
+        None => builder,
+    };
+
+    if let Some(secs) = network.connect_timeout {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+
+    if let Some(path) = &network.ca_bundle {
+        let pem =
+            fs::read(path).map_err(|e| LLMError::Config(format!("ca_bundle {:?}: {}", path, e)))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| LLMError::Config(format!("ca_bundle {:?}: {}", path, e)))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    builder = builder.danger_accept_invalid_certs(network.insecure);
+
+    builder = match network.http {
+        HttpVersion::Auto => builder,
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::H2c => builder.http2_prior_knowledge(),
+    };
+
+    Ok(builder.build()?)
+}
+
+/// Whether `url` points at this machine.
+fn is_loopback(url: Option<&str>) -> bool {
+    let Some(url) = url.and_then(|url| reqwest::Url::parse(url).ok()) else {
+        return false;
+    };
+    match url.host_str() {
+        Some("localhost") => true,
+        Some(host) => host
+            .trim_matches(['[', ']'])
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback()),
+        None => false,
+    }
+}
+
+/// The next chunk of a streamed response, or a timeout once the server
+/// stayed silent for `read_timeout`.
+pub async fn chunk(
+    res: &mut Response,
+    read_timeout: Option<Duration>,
+) -> Result<Option<Bytes>, LLMError> {
+    match read_timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, res.chunk()).await {
+            Ok(chunk) => Ok(chunk?),
+            Err(_) => Err(LLMError::Timeout),
+        },
+        None => Ok(res.chunk().await?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::{self, StubResponse};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    fn profile(backend: &str, network: &str) -> Profile {
+        stub::profile_with(backend, &format!("[network]\n{}", network))
+    }
+
+    #[test]
+    fn loopback_urls_are_recognized() {
+        assert!(is_loopback(Some("http://localhost:11434/api/chat")));
+        assert!(is_loopback(Some("http://127.0.0.1:8000/v1")));
+        assert!(is_loopback(Some("http://[::1]:8080")));
+        assert!(!is_loopback(Some("https://api.openai.com/v1")));
+        assert!(!is_loopback(None));
+    }
+
+    #[tokio::test]
+    async fn proxy_is_used_and_silent_streams_time_out() {
+        let (proxy, server) = stub::serve(vec![StubResponse::new("200 OK", "proxied")]).await;
+        let proxied = client(&profile("chatgpt", &format!("proxy = \"{}\"", proxy))).unwrap();
+        let res = proxied
+            .get("http://backend.invalid/v1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "proxied");
+        let requests = server.await.unwrap();
+        assert!(requests[0]
+            .head
+            .starts_with("GET http://backend.invalid/v1 "));
+
+        let broken = profile("infinilm", "ca_bundle = \"/nonexistent/ca.pem\"");
+        assert!(matches!(client(&broken).unwrap_err(), LLMError::Config(_)));
+
+        // Half a body, then nothing
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+        let direct = client(&profile("infinilm", "")).unwrap();
+        let mut res = direct.get(&url).send().await.unwrap();
+        let timeout = Some(Duration::from_millis(200));
+        assert_eq!(
+            chunk(&mut res, timeout).await.unwrap().as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(chunk(&mut res, timeout).await, Err(LLMError::Timeout));
+        server.abort();
+    }
+}
@@ -0,0 +1,277 @@
+//! Model management endpoints of an Ollama server: listing, pulling and unloading.
+
+use super::*;
+
+/// A model installed on the server, as shown in the model picker.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelInfo {
+    pub name: String,
+    /// Size on disk in bytes.
+    pub size: u64,
+    pub parameter_size: Option<String>,
+    pub quantization: Option<String>,
+    /// Context length the model was trained with, from `/api/show`.
+    pub context_length: Option<u64>,
+    /// Loaded in memory, per `/api/ps`.
+    pub loaded: bool,
+}
+
+/// Progress of a model download.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PullProgress {
+    pub model: String,
+    pub status: String,
+    pub completed: Option<u64>,
+    pub total: Option<u64>,
+}
+
+impl std::fmt::Display for PullProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.model, self.status)?;
+        if let (Some(completed), Some(total)) = (self.completed, self.total) {
+            write!(f, " {}%", completed * 100 / total.max(1))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ModelEvent {
+    /// The models of the server, for the picker.
+    Listed(Vec<ModelInfo>),
+    /// A line of `/api/pull` progress, `None` once the pull ended.
+    Pull(Option<PullProgress>),
+}
+
+#[derive(Clone, Debug)]
+pub struct OllamaApi {
+    http: net::Http,
+    /// The server root, e.g. `http://localhost:11434`.
+    base_url: String,
+}
+
+impl OllamaApi {
+    pub fn new(profile: &Profile) -> Result<Self, LLMError> {
+        let url = profile
+            .url
+            .clone()
+            .unwrap_or_else(OllamaConfig::default_url);
+        let base_url = match url.find("/api/") {
+            Some(i) => url[..i].to_string(),
+            None => url.trim_end_matches('/').to_string(),
+        };
+
+        Ok(Self {
+            http: net::Http::new(profile)?,
+            base_url,
+        })
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/api/{}", self.base_url, path)
+    }
+
+    async fn tags(&self, sender: &UnboundedSender<Event>) -> Result<Value, LLMError> {
+        let request = self.http.client.get(self.endpoint("tags"));
+        Ok(error::send(request, sender).await?.json().await?)
+    }
+
+    /// Names of the installed models, from a single request that isn't retried.
+    pub async fn names(&self) -> Result<Vec<String>, LLMError> {
+        let request = self.http.client.get(self.endpoint("tags"));
+        let tags: Value = error::send_once(request).await?.json().await?;
+        Ok(tags["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|model| model["name"].as_str().map(String::from))
+            .collect())
+    }
+
+    /// Installed models, with their details and whether they are loaded.
+    pub async fn models(
+        &self,
+        sender: &UnboundedSender<Event>,
+    ) -> Result<Vec<ModelInfo>, LLMError> {
+        let tags = self.tags(sender).await?;
+
+        let request = self.http.client.get(self.endpoint("ps"));
+        let running: Value = error::send(request, sender).await?.json().await?;
+        let loaded: Vec<&str> = running["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|model| model["name"].as_str())
+            .collect();
+
+        // The `/api/show` lookups run together, a model whose lookup fails is
+        // listed without its context length
+        let models = tags["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|model| {
+                let loaded = &loaded;
+                async move {
+                    let name = model["name"].as_str().unwrap_or_default().to_string();
+                    let details = &model["details"];
+                    ModelInfo {
+                        size: model["size"].as_u64().unwrap_or_default(),
+                        parameter_size: details["parameter_size"].as_str().map(String::from),
+                        quantization: details["quantization_level"].as_str().map(String::from),
+                        context_length: self.context_length(&name).await.ok().flatten(),
+                        loaded: loaded.contains(&name.as_str()),
+                        name,
+                    }
+                }
+            });
+
+        Ok(futures::future::join_all(models).await)
+    }
+
+    /// Reads `<architecture>.context_length` from the model's `/api/show` info,
+    /// asked once since the model is listed without it anyway.
+    async fn context_length(&self, model: &str) -> Result<Option<u64>, LLMError> {
+        let request = self
+            .http
+            .client
+            .post(self.endpoint("show"))
+            .json(&json!({ "model": model }));
+        let show: Value = error::send_once(request).await?.json().await?;
+
+        Ok(show["model_info"].as_object().and_then(|info| {
+            info.iter()
+                .find(|(key, _)| key.ends_with(".context_length"))
+                .and_then(|(_, value)| value.as_u64())
+        }))
+    }
+
+    /// Downloads `model`, streaming the progress to the model picker.
+    pub async fn pull(&self, model: &str, sender: &UnboundedSender<Event>) -> Result<(), LLMError> {
+        let request = self
+            .http
+            .client
+            .post(self.endpoint("pull"))
+            .json(&json!({ "model": model, "stream": true }));
+        let mut res = error::send(request, sender).await?;
+
+        let mut buffer = Vec::new();
+        while let Some(chunk) = net::chunk(&mut res, self.http.read_timeout).await? {
+            buffer.extend_from_slice(&chunk);
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let line: Value = serde_json::from_slice(&line)?;
+                if let Some(error) = line["error"].as_str() {
+                    return Err(LLMError::Backend(error.to_string()));
+                }
+                let progress = PullProgress {
+                    model: model.to_string(),
+                    status: line["status"].as_str().unwrap_or_default().to_string(),
+                    completed: line["completed"].as_u64(),
+                    total: line["total"].as_u64(),
+                };
+                sender.send(Event::Models(ModelEvent::Pull(Some(progress))))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Frees the memory of `model` right away, with a `keep_alive` of 0.
+    pub async fn unload(
+        &self,
+        model: &str,
+        sender: &UnboundedSender<Event>,
+    ) -> Result<(), LLMError> {
+        let request = self
+            .http
+            .client
+            .post(self.endpoint("generate"))
+            .json(&json!({ "model": model, "keep_alive": 0 }));
+        error::send(request, sender).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::{self, StubResponse};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn models_are_listed_with_their_details() {
+        let tags = r#"{"models":[{"name":"qwen2:7b","size":4431400262,"details":{"parameter_size":"7.6B","quantization_level":"Q4_0"}},{"name":"llama3:8b","size":4661224676,"details":{"parameter_size":"8.0B","quantization_level":"Q4_0"}}]}"#;
+        let ps = r#"{"models":[{"name":"llama3:8b","size_vram":5137025024}]}"#;
+        let (url, server) = stub::serve(vec![
+            StubResponse::new("200 OK", tags),
+            StubResponse::new("200 OK", ps),
+            // The lookups run together, either model can get either answer
+            StubResponse::new("200 OK", r#"{"model_info":{"qwen2.context_length":32768}}"#),
+            StubResponse::new("200 OK", r#"{"model_info":{"llama.context_length":32768}}"#),
+        ])
+        .await;
+        let profile = stub::profile("ollama", &format!("{}/api/chat", url));
+
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let models = OllamaApi::new(&profile)
+            .unwrap()
+            .models(&sender)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            models,
+            vec![
+                ModelInfo {
+                    name: "qwen2:7b".to_string(),
+                    size: 4431400262,
+                    parameter_size: Some("7.6B".to_string()),
+                    quantization: Some("Q4_0".to_string()),
+                    context_length: Some(32768),
+                    loaded: false,
+                },
+                ModelInfo {
+                    name: "llama3:8b".to_string(),
+                    size: 4661224676,
+                    parameter_size: Some("8.0B".to_string()),
+                    quantization: Some("Q4_0".to_string()),
+                    context_length: Some(32768),
+                    loaded: true,
+                },
+            ]
+        );
+        let requests = server.await.unwrap();
+        assert!(requests[0].head.starts_with("GET /api/tags "));
+        assert!(requests[2].head.starts_with("POST /api/show "));
+        let mut shown: Vec<&Value> = requests[2..].iter().map(|r| &r.body["model"]).collect();
+        shown.sort_by_key(|model| model.to_string());
+        assert_eq!(shown, [&json!("llama3:8b"), &json!("qwen2:7b")]);
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_leave_the_context_length_out() {
+        let tags = r#"{"models":[{"name":"qwen2:7b","size":4431400262,"details":{}}]}"#;
+        let (url, _server) = stub::serve(vec![
+            StubResponse::new("200 OK", tags),
+            StubResponse::new("200 OK", r#"{"models":[]}"#),
+            StubResponse::new("404 Not Found", r#"{"error":"model not found"}"#),
+        ])
+        .await;
+        let profile = stub::profile("ollama", &format!("{}/api/chat", url));
+
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let models = OllamaApi::new(&profile)
+            .unwrap()
+            .models(&sender)
+            .await
+            .unwrap();
+
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].context_length, None);
+    }
+}
@@ -0,0 +1,296 @@
+use super::*;
+
+use crate::config::{GenerationConfig, OllamaConfig, OllamaOptions, Profile};
+use crate::llm::context::ContextBudget;
+use crate::llm::tools::{ToolCall, ToolFormat, ToolLoop, Tools, Turn};
+
+pub mod api;
+
+pub use self::api::{ModelEvent, ModelInfo, OllamaApi, PullProgress};
+
+#[derive(Clone, Debug)]
+pub struct Ollama {
+    http: net::Http,
+    url: String,
+    model: String,
+    generation: GenerationConfig,
+    options: OllamaOptions,
+    context: ContextBudget,
+    tools: Option<Tools>,
+}
+
+impl Ollama {
+    pub fn new(profile: &Profile, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let model = profile
+            .model
+            .clone()
+            .ok_or_else(|| format!("Profile `{}` needs a model for Ollama", profile.name))?;
+
+        Ok(Self {
+            http: net::Http::new(profile)?,
+            url: profile
+                .url
+                .clone()
+                .unwrap_or_else(OllamaConfig::default_url),
+            model,
+            generation: profile.generation.or(&config.generation),
+            options: profile.ollama.clone(),
+            context: ContextBudget::new(profile, config),
+            tools: profile
+                .tools
+                .then(|| Tools::new(&config.file_explorer_path)),
+        })
+    }
+
+    /// Like `new`, taking the first model installed on the server when the
+    /// profile doesn't name one.
+    pub async fn discover(
+        profile: &Profile,
+        config: &Config,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if profile.model.is_some() {
+            return Self::new(profile, config);
+        }
+
+        // Awaited by the UI, so a stopped server fails right away
+        let models = OllamaApi::new(profile)?.names().await?;
+        let model = models.into_iter().next().ok_or_else(|| {
+            format!(
+                "Profile `{}` has no model and Ollama has none installed, pull one with `ollama pull`",
+                profile.name
+            )
+        })?;
+
+        let profile = Profile {
+            model: Some(model),
+            ..profile.clone()
+        };
+        Self::new(&profile, config)
+    }
+}
+
+#[async_trait]
+impl LLM for Ollama {
+    fn backend(&self) -> LLMBackend {
+        LLMBackend::Ollama
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn supports_images(&self) -> bool {
+        true
+    }
+
+    async fn ask(
+        &self,
+        messages: &[Message],
+        sender: UnboundedSender<Event>,
+    ) -> Result<(), LLMError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "application/json".parse()?);
+
+        let mut messages = self
+            .context
+            .request_messages(messages, &sender)?
+            .iter()
+            .map(request_message)
+            .collect::<Result<Vec<Value>, _>>()?;
+        let mut tool_loop = self
+            .tools
+            .as_ref()
+            .map(|tools| ToolLoop::new(tools, ToolFormat::Ollama));
+
+        for round in 0.. {
+            let mut body: Value = json!({
+                "messages": messages,
+                "model": self.model,
+                "stream": true,
+                "options": options(&self.generation, &self.options),
+            });
+            if let Some(keep_alive) = &self.options.keep_alive {
+                body["keep_alive"] = json!(keep_alive);
+            }
+            if let Some(tools) = &self.tools {
+                body["tools"] = tools.definitions();
+            }
+
+            let request = self
+                .http
+                .client
+                .post(&self.url)
+                .headers(headers.clone())
+                .json(&body);
+
+            let res = error::send(request, &sender).await?;
+            if round == 0 {
+                sender.send(Event::LLMEvent(LLMAnswer::StartAnswer))?;
+            }
+            let turn = stream_chat(res, self.http.read_timeout, &sender).await?;
+
+            let Some(tool_loop) = tool_loop.as_mut() else {
+                break;
+            };
+            if !tool_loop.feed_back(&mut messages, turn, &sender).await? {
+                break;
+            }
+        }
+        sender.send(Event::LLMEvent(LLMAnswer::EndAnswer))?;
+
+        Ok(())
+    }
+}
+
+/// A message of the request, its images base64 encoded in `images`.
+fn request_message(message: &Message) -> Result<Value, serde_json::Error> {
+    let mut value = serde_json::to_value(message)?;
+    if !message.attachments.is_empty() {
+        let images: Vec<&str> = message
+            .attachments
+            .iter()
+            .map(|image| &*image.data)
+            .collect();
+        value["images"] = json!(images);
+    }
+    Ok(value)
+}
+
+/// Forwards a streamed `/api/chat` response to the UI, and collects the tool
+/// calls of the model, which Ollama sends whole.
+async fn stream_chat(
+    mut res: reqwest::Response,
+    read_timeout: Option<Duration>,
+    sender: &UnboundedSender<Event>,
+) -> Result<Turn, LLMError> {
+    let mut turn = Turn::default();
+
+    // One JSON object per line, a line can span several chunks
+    let mut buffer = Vec::new();
+    while let Some(chunk) = net::chunk(&mut res, read_timeout).await? {
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            if read_answer(&serde_json::from_slice(&line)?, &mut turn, sender)? {
+                return Ok(turn);
+            }
+        }
+    }
+    if !buffer.iter().all(u8::is_ascii_whitespace) {
+        read_answer(&serde_json::from_slice(&buffer)?, &mut turn, sender)?;
+    }
+
+    Ok(turn)
+}
+
+/// Adds a line of the answer to `turn`, true once it is the last one.
+fn read_answer(
+    answer: &Value,
+    turn: &mut Turn,
+    sender: &UnboundedSender<Event>,
+) -> Result<bool, LLMError> {
+    // A failure after the response started comes as a line of its own
+    if let Some(error) = answer["error"].as_str() {
+        return Err(LLMError::Backend(error.to_string()));
+    }
+
+    if let Some(calls) = answer["message"]["tool_calls"].as_array() {
+        for call in calls {
+            turn.tool_calls.push(ToolCall {
+                id: format!("call_{}", turn.tool_calls.len()),
+                name: call["function"]["name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                arguments: call["function"]["arguments"].clone(),
+            });
+        }
+    }
+
+    if answer["done"].as_bool().unwrap_or(false) {
+        if let (Some(prompt), Some(completion)) = (
+            answer["prompt_eval_count"].as_u64(),
+            answer["eval_count"].as_u64(),
+        ) {
+            let usage = Usage::reported(prompt, completion);
+            sender.send(Event::LLMEvent(LLMAnswer::Usage(usage)))?;
+        }
+        return Ok(true);
+    }
+
+    let msg = answer["message"]["content"].as_str().unwrap_or_default();
+    if !msg.is_empty() {
+        turn.content.push_str(msg);
+        sender.send(Event::LLMEvent(LLMAnswer::Answer(msg.to_string())))?;
+    }
+
+    Ok(false)
+}
+
+/// Maps the generation and runner settings onto Ollama's `options` object.
+fn options(generation: &GenerationConfig, ollama: &OllamaOptions) -> Value {
+    let mut options = json!({});
+    set_opt(&mut options, "num_ctx", &ollama.num_ctx);
+    set_opt(&mut options, "num_gpu", &ollama.num_gpu);
+    set_opt(&mut options, "temperature", &generation.temperature);
+    set_opt(&mut options, "top_p", &generation.top_p);
+    set_opt(&mut options, "top_k", &generation.top_k);
+    set_opt(&mut options, "num_predict", &generation.max_tokens);
+    set_opt(&mut options, "stop", &generation.stop);
+    set_opt(&mut options, "seed", &generation.seed);
+    set_opt(
+        &mut options,
+        "presence_penalty",
+        &generation.presence_penalty,
+    );
+    set_opt(
+        &mut options,
+        "frequency_penalty",
+        &generation.frequency_penalty,
+    );
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::{self, StubResponse};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn lines_split_across_chunks_are_joined() {
+        // Chunked transfer encoding, the second line is cut in two chunks
+        let body = "1e\r\n{\"message\":{\"content\":\"Hel\"}}\n\r\n\
+                    b\r\n{\"message\":\r\n\
+                    12\r\n{\"content\":\"lo\"}}\n\r\n\
+                    4c\r\n{\"done\":true,\"prompt_eval_count\":3,\"eval_count\":2,\"message\":{\"content\":\"\"}}\n\r\n\
+                    0\r\n\r\n";
+        let (url, _server) = stub::serve(vec![
+            StubResponse::new("200 OK", body).header("Transfer-Encoding", "chunked")
+        ])
+        .await;
+        let res = reqwest::get(url).await.unwrap();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let turn = stream_chat(res, None, &sender).await.unwrap();
+        assert_eq!(turn.content, "Hello");
+        assert_eq!(stub::collect_answer(&mut receiver), "Hello");
+    }
+
+    #[tokio::test]
+    async fn error_lines_end_the_answer() {
+        let body = "{\"message\":{\"content\":\"Hel\"}}\n{\"error\":\"model runner crashed\"}\n";
+        let (url, _server) = stub::serve(vec![StubResponse::new("200 OK", body)]).await;
+        let res = reqwest::get(url).await.unwrap();
+
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        assert_eq!(
+            stream_chat(res, None, &sender).await.unwrap_err(),
+            LLMError::Backend("model runner crashed".to_string())
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Named system-prompt presets. Each `prompts/<name>.toml` of the config
+//! directory holds the `[[messages]]` sent ahead of a conversation; the older
+//! single `prompt.toml` is the `default` preset unless `prompts/default.toml`
+//! exists. Message contents are handlebars templates, rendered when sent.
+
+use super::*;
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use handlebars::{
+    no_escape, Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext,
+    RenderErrorReason,
+};
+
+use crate::config::config_dir;
+
+pub const DEFAULT_PRESET: &str = "default";
+
+#[derive(Clone)]
+struct Cached {
+    modified: Option<SystemTime>,
+    /// The prompts of the last file that was read without error.
+    messages: Option<Vec<Message>>,
+    /// Why the file as of `modified` could not be read.
+    error: Option<String>,
+}
+
+/// Presets by file, kept until the file's modification time changes.
+static CACHE: Mutex<BTreeMap<PathBuf, Cached>> = Mutex::new(BTreeMap::new());
+
+/// Names of the presets in `dir`, `default` first.
+pub fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir.join("prompts"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+        .filter(|name| name != DEFAULT_PRESET)
+        .collect();
+    names.sort();
+    names.insert(0, DEFAULT_PRESET.to_string());
+    names
+}
+
+fn path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join("prompts").join(format!("{}.toml", name));
+    // A file that can't be checked is taken as there, its error is reported
+    if name == DEFAULT_PRESET && !path.try_exists().unwrap_or(true) {
+        return dir.join("prompt.toml");
+    }
+    path
+}
+
+/// Prompts of the `default` preset when there is no file for it.
+fn builtin() -> Vec<Message> {
+    vec![
+        Message::new(
+            LLMRole::SYSTEM,
+            "你是一个 ai 助手，为用户解决问题".to_string(),
+        ),
+        Message::new(LLMRole::ASSISTANT, "请一步步思考".to_string()),
+    ]
+}
+
+/// The cache entry of preset `name` in `dir`, read again only when the file
+/// changed. A file that doesn't parse leaves the previous prompts in place, a
+/// removed one drops them.
+fn cached(dir: &Path, name: &str) -> Cached {
+    let path = path(dir, name);
+    let metadata = fs::metadata(&path);
+    let modified = metadata.as_ref().ok().and_then(|m| m.modified().ok());
+    let missing = matches!(&metadata, Err(e) if e.kind() == ErrorKind::NotFound);
+
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(cached) = cache.get(&path) {
+        if cached.modified == modified {
+            return cached.clone();
+        }
+    }
+
+    let read = match metadata {
+        Ok(_) => read_messages_from_toml(&path.to_string_lossy()),
+        // Only a missing file means the built-in prompts, not one that can't be read
+        Err(e) if !missing => Err(format!("{:?}: {}", path, e)),
+        Err(_) if name == DEFAULT_PRESET => Ok(builtin()),
+        Err(_) => Err(format!("there is no prompt preset {:?}", path)),
+    }
+    .map(|mut messages| {
+        for message in &mut messages {
+            message.preset = true;
+        }
+        messages
+    });
+    let previous = cache
+        .remove(&path)
+        .and_then(|cached| cached.messages)
+        .filter(|_| !missing);
+    let cached = match read {
+        Ok(messages) => Cached {
+            modified,
+            messages: Some(messages),
+            error: None,
+        },
+        Err(e) => Cached {
+            modified,
+            messages: previous,
+            error: Some(e),
+        },
+    };
+    cache.insert(path, cached.clone());
+    cached
+}
+
+/// The prompts of preset `name` in `dir`, the last ones read without error
+/// when its file is broken.
+pub fn prompts(dir: &Path, name: &str) -> Result<Vec<Message>, String> {
+    let cached = cached(dir, name);
+    cached
+        .messages
+        .ok_or_else(|| cached.error.unwrap_or_default())
+}
+
+/// Reads preset `name` again after its file changed, the error if it can't be
+/// read.
+pub fn reload(dir: &Path, name: &str) -> Result<(), String> {
+    let cached = cached(dir, name);
+    match cached.error {
+        Some(e) if cached.messages.is_some() => Err(format!("{}, keeping the previous prompts", e)),
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Values of the template variables: `date`, `cwd`, `os`, `model` and `file_explorer_path`.
+pub fn variables(model: &str, file_explorer_path: &str) -> Value {
+    json!({
+        "date": Local::now().format("%Y-%m-%d").to_string(),
+        "cwd": env::current_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default(),
+        "os": env::consts::OS,
+        "model": model,
+        "file_explorer_path": file_explorer_path,
+    })
+}
+
+/// `{{include "path"}}`: the contents of the file, read when the prompt is
+/// sent. A relative path starts from `dir`, the config directory.
+struct Include<'a> {
+    dir: &'a Path,
+}
+
+impl HelperDef for Include<'_> {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let path = h
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("include", 0))?;
+        let contents = fs::read_to_string(self.dir.join(path))
+            .map_err(|e| RenderErrorReason::Other(format!("include \"{}\": {}", path, e)))?;
+        out.write(&contents)?;
+        Ok(())
+    }
+}
+
+/// Renders the contents of `prompts` as handlebars templates, included files
+/// relative to `dir`. Unknown variables are errors, so typos don't reach the
+/// model; a literal `{{` is written `\{{`.
+pub fn render(prompts: &mut [Message], variables: &Value, dir: &Path) -> Result<(), String> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(no_escape);
+    handlebars.set_strict_mode(true);
+    handlebars.register_helper("include", Box::new(Include { dir }));
+
+    for message in prompts {
+        message.content = handlebars
+            .render_template(&message.content, variables)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// The rendered prompts of preset `name` followed by the conversation, as
+/// sent to a backend.
+pub fn request_messages(
+    name: &str,
+    history: &[Message],
+    variables: &Value,
+) -> Result<Vec<Message>, String> {
+    let dir = config_dir();
+    let mut messages = prompts(&dir, name)?;
+    render(&mut messages, variables, &dir)
+        .map_err(|e| format!("prompt preset `{}`: {}", name, e))?;
+    messages.extend_from_slice(history);
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::TempDir;
+
+    #[test]
+    fn presets_are_listed_and_reloaded_when_changed() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.join("prompts")).unwrap();
+        fs::write(
+            dir.join("prompt.toml"),
+            "[[messages]]\nrole = \"system\"\ncontent = \"legacy\"\n",
+        )
+        .unwrap();
+        let reviewer = dir.join("prompts").join("reviewer.toml");
+        fs::write(
+            &reviewer,
+            "[[messages]]\nrole = \"system\"\ncontent = \"review\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(names(&dir), vec!["default", "reviewer"]);
+        assert_eq!(prompts(&dir, "default").unwrap()[0].content, "legacy");
+        let review = prompts(&dir, "reviewer").unwrap();
+        assert_eq!(review[0].content, "review");
+        assert!(review[0].preset);
+
+        // Same file, same messages; a new modification time reloads it
+        assert_eq!(prompts(&dir, "reviewer").unwrap()[0].id, review[0].id);
+        fs::write(
+            &reviewer,
+            "[[messages]]\nrole = \"system\"\ncontent = \"strict review\"\n",
+        )
+        .unwrap();
+        let file = fs::File::options().write(true).open(&reviewer).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            prompts(&dir, "reviewer").unwrap()[0].content,
+            "strict review"
+        );
+        assert!(prompts(&dir, "missing").is_err());
+
+        // A broken file keeps the last prompts, its error is reported on reload
+        fs::write(&reviewer, "[[messages]]\nrole = \"system\"\n").unwrap();
+        let file = fs::File::options().write(true).open(&reviewer).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            prompts(&dir, "reviewer").unwrap()[0].content,
+            "strict review"
+        );
+        assert!(reload(&dir, "reviewer")
+            .unwrap_err()
+            .contains("reviewer.toml"));
+
+        fs::remove_file(&reviewer).unwrap();
+        assert!(prompts(&dir, "reviewer").is_err());
+    }
+
+    #[test]
+    fn templates_are_rendered_with_includes() {
+        let dir = TempDir::new();
+        fs::write(dir.join("notes.md"), "use tabs").unwrap();
+        let variables = variables("qwen2:7b", "/src");
+
+        // Relative to the config directory, not the current one
+        let mut prompts = vec![Message::new(
+            LLMRole::SYSTEM,
+            "{{model}} on {{os}}, {{file_explorer_path}}: {{include \"notes.md\"}} <ok>"
+                .to_string(),
+        )];
+        render(&mut prompts, &variables, &dir).unwrap();
+        assert_eq!(
+            prompts[0].content,
+            format!("qwen2:7b on {}, /src: use tabs <ok>", env::consts::OS)
+        );
+
+        let mut typo = vec![Message::new(LLMRole::SYSTEM, "{{modle}}".to_string())];
+        assert!(render(&mut typo, &variables, &dir).is_err());
+        let mut escaped = vec![Message::new(
+            LLMRole::SYSTEM,
+            "format!(\"\\{{name}}\")".to_string(),
+        )];
+        render(&mut escaped, &variables, &dir).unwrap();
+        assert_eq!(escaped[0].content, "format!(\"{{name}}\")");
+        let mut missing = vec![Message::new(
+            LLMRole::SYSTEM,
+            "{{include \"/nonexistent/notes.md\"}}".to_string(),
+        )];
+        assert!(render(&mut missing, &variables, &dir)
+            .unwrap_err()
+            .contains("/nonexistent/notes.md"));
+    }
+}
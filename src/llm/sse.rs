@@ -0,0 +1,393 @@
+//! Server-Sent Events decoding for the OpenAI-style streaming endpoints.
+//!
+//! Follows the WHATWG event stream format: `data:` fields spanning several
+//! lines, `event:`/`id:` fields, comments and any of `\n`, `\r\n` or `\r` as
+//! line terminator. Bytes are buffered until a full line is available, so a
+//! UTF-8 sequence split across network chunks is decoded intact.
+
+use super::*;
+
+use self::tools::{ToolCall, Turn};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SseEvent {
+    /// The `event:` field, `None` for the default `message` type.
+    pub event: Option<String>,
+    pub data: String,
+    /// The last `id:` seen on the stream.
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    started: bool,
+    event: Option<String>,
+    data: Vec<String>,
+    last_id: Option<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a chunk of the response body and returns the events it completes.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        if !self.started {
+            if self.buffer.len() < 3 && b"\xEF\xBB\xBF".starts_with(&self.buffer) {
+                return Vec::new();
+            }
+            if self.buffer.starts_with(b"\xEF\xBB\xBF") {
+                self.buffer.drain(..3);
+            }
+            self.started = true;
+        }
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.buffer.len() {
+            let end = match self.buffer[i] {
+                b'\n' => i + 1,
+                // A trailing `\r` may be the first half of a `\r\n`.
+                b'\r' if i + 1 == self.buffer.len() => break,
+                b'\r' if self.buffer[i + 1] == b'\n' => i + 2,
+                b'\r' => i + 1,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let line = String::from_utf8_lossy(&self.buffer[start..i]).into_owned();
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+            start = end;
+            i = end;
+        }
+        self.buffer.drain(..start);
+
+        events
+    }
+
+    /// Ends the stream. An event without its terminating blank line was cut
+    /// off, so it is discarded as the spec requires.
+    pub fn finish(&mut self) {
+        let rest = std::mem::take(&mut self.buffer);
+        if !self.data.is_empty() || !rest.is_empty() {
+            info!("Discarding an unterminated event at the end of the stream");
+        }
+        self.event = None;
+        self.data.clear();
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => self.data.push(value.to_string()),
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            _ => {}
+        }
+
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+
+        Some(SseEvent {
+            event,
+            data: std::mem::take(&mut self.data).join("\n"),
+            id: self.last_id.clone(),
+        })
+    }
+}
+
+/// A decoded piece of an OpenAI-style `chat.completion.chunk` stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamDelta {
+    Content(String),
+    /// Pieces of tool calls, the arguments of a call are spread over several chunks.
+    ToolCalls(Vec<ToolCallDelta>),
+    Usage(Usage),
+    Done,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub arguments: String,
+}
+
+impl StreamDelta {
+    /// Decodes one event into every delta it carries, none for chunks without
+    /// content (e.g. role only). An `error` chunk, sent by servers that fail
+    /// after the response started, is a `Backend` error.
+    pub fn from_openai(event: &SseEvent) -> Result<Vec<Self>, LLMError> {
+        if event.data.trim() == "[DONE]" {
+            return Ok(vec![StreamDelta::Done]);
+        }
+
+        let chunk: Value = serde_json::from_str(&event.data)?;
+        let error = &chunk["error"];
+        if !error.is_null() {
+            let message = error.as_str().or(error["message"].as_str());
+            return Err(LLMError::Backend(
+                message.map_or_else(|| error.to_string(), String::from),
+            ));
+        }
+        let delta = &chunk["choices"][0]["delta"];
+        let mut deltas = Vec::new();
+
+        if let Some(content) = delta["content"].as_str().filter(|c| !c.is_empty()) {
+            deltas.push(StreamDelta::Content(content.to_string()));
+        }
+        if let Some(calls) = delta["tool_calls"].as_array() {
+            deltas.push(StreamDelta::ToolCalls(
+                calls
+                    .iter()
+                    .map(|call| ToolCallDelta {
+                        index: call["index"].as_u64().unwrap_or(0) as usize,
+                        id: call["id"].as_str().map(String::from),
+                        name: call["function"]["name"].as_str().map(String::from),
+                        arguments: call["function"]["arguments"]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_string(),
+                    })
+                    .collect(),
+            ));
+        }
+        // Some servers send the usage on the last content chunk
+        if let Some(usage) = Usage::from_openai(&chunk["usage"]) {
+            deltas.push(StreamDelta::Usage(usage));
+        }
+
+        Ok(deltas)
+    }
+}
+
+/// Forwards the content of an OpenAI-style SSE response to the UI, and
+/// collects the tool calls the model made.
+pub async fn stream_openai(
+    mut res: reqwest::Response,
+    read_timeout: Option<Duration>,
+    sender: &UnboundedSender<Event>,
+) -> Result<Turn, LLMError> {
+    let mut decoder = SseDecoder::new();
+    let mut turn = Turn::default();
+    let mut calls: Vec<ToolCallDelta> = Vec::new();
+
+    let mut handle = |delta: StreamDelta| -> Result<bool, LLMError> {
+        match delta {
+            StreamDelta::Content(content) => {
+                turn.content.push_str(&content);
+                sender.send(Event::LLMEvent(LLMAnswer::Answer(content)))?;
+            }
+            StreamDelta::ToolCalls(deltas) => {
+                for delta in deltas {
+                    match calls.iter_mut().find(|call| call.index == delta.index) {
+                        Some(call) => {
+                            call.id = call.id.take().or(delta.id);
+                            call.name = call.name.take().or(delta.name);
+                            call.arguments.push_str(&delta.arguments);
+                        }
+                        None => calls.push(delta),
+                    }
+                }
+            }
+            StreamDelta::Usage(usage) => {
+                sender.send(Event::LLMEvent(LLMAnswer::Usage(usage)))?;
+            }
+            StreamDelta::Done => return Ok(true),
+        }
+        Ok(false)
+    };
+
+    'stream: while let Some(chunk) = net::chunk(&mut res, read_timeout).await? {
+        for event in decoder.feed(&chunk) {
+            for delta in StreamDelta::from_openai(&event)? {
+                if handle(delta)? {
+                    break 'stream;
+                }
+            }
+        }
+    }
+    decoder.finish();
+
+    turn.tool_calls = calls
+        .into_iter()
+        .map(|call| ToolCall {
+            id: call.id.unwrap_or_else(|| format!("call_{}", call.index)),
+            name: call.name.unwrap_or_default(),
+            arguments: serde_json::from_str(&call.arguments)
+                .unwrap_or(Value::String(call.arguments)),
+        })
+        .collect();
+
+    Ok(turn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recorded OpenAI stream, including a role-only first chunk and the
+    /// `include_usage` chunk.
+    const OPENAI_STREAM: &str = concat!(
+        "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"fn main() {\\n\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"    println!(\\\"你好 🦀\\\");\\n}\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"choices\":[],\"usage\":{\"prompt_tokens\":21,\"completion_tokens\":14,\"total_tokens\":35}}\n\n",
+        "data: [DONE]\n\n",
+    );
+
+    /// Recorded ChatGLM stream, which uses CRLF and escaped newlines.
+    const CHATGLM_STREAM: &str = concat!(
+        "data: {\"id\":\"8530388547035158662\",\"created\":1711946953,\"model\":\"glm-4\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"安全\"}}]}\r\n\r\n",
+        "data: {\"id\":\"8530388547035158662\",\"created\":1711946953,\"model\":\"glm-4\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"的 Rust\\n\\n代码 😀\"}}]}\r\n\r\n",
+        "data: [DONE]\r\n\r\n",
+    );
+
+    fn decode_all(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let events: Vec<SseEvent> = chunks.iter().flat_map(|c| decoder.feed(c)).collect();
+        decoder.finish();
+        events
+    }
+
+    fn contents(events: &[SseEvent]) -> Vec<StreamDelta> {
+        events
+            .iter()
+            .flat_map(|e| StreamDelta::from_openai(e).unwrap())
+            .collect()
+    }
+
+    /// Every two-chunk split, and a byte-at-a-time feed, decode like the whole body.
+    fn assert_split_invariant(stream: &str) -> Vec<SseEvent> {
+        let bytes = stream.as_bytes();
+        let expected = decode_all(&[bytes]);
+
+        for at in 0..bytes.len() {
+            let (head, tail) = bytes.split_at(at);
+            assert_eq!(decode_all(&[head, tail]), expected, "split at byte {}", at);
+        }
+
+        let single: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!(decode_all(&single), expected);
+
+        expected
+    }
+
+    #[test]
+    fn openai_stream_survives_any_split() {
+        let events = assert_split_invariant(OPENAI_STREAM);
+        assert_eq!(
+            contents(&events),
+            vec![
+                StreamDelta::Content("fn main() {\n".to_string()),
+                StreamDelta::Content("    println!(\"你好 🦀\");\n}".to_string()),
+                StreamDelta::Usage(Usage::reported(21, 14)),
+                StreamDelta::Done,
+            ]
+        );
+    }
+
+    #[test]
+    fn chatglm_stream_is_json_decoded() {
+        let events = assert_split_invariant(CHATGLM_STREAM);
+        assert_eq!(
+            contents(&events),
+            vec![
+                StreamDelta::Content("安全".to_string()),
+                StreamDelta::Content("的 Rust\n\n代码 😀".to_string()),
+                StreamDelta::Done,
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_line_data_with_event_and_id() {
+        let stream = "\u{feff}: keep-alive\r\nevent: message_delta\rid: 7\ndata: first\ndata:second\n\ndata: third\n\n";
+        let events = assert_split_invariant(stream);
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: Some("message_delta".to_string()),
+                    data: "first\nsecond".to_string(),
+                    id: Some("7".to_string()),
+                },
+                SseEvent {
+                    event: None,
+                    data: "third".to_string(),
+                    id: Some("7".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_final_event_is_discarded() {
+        let stream = concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"the"
+        );
+        let events = assert_split_invariant(stream);
+        assert_eq!(
+            contents(&events),
+            vec![StreamDelta::Content("hi".to_string())]
+        );
+        // Complete fields without the blank line are cut off too
+        assert!(decode_all(&[b"data: tail\n"]).is_empty());
+        assert!(decode_all(&[b"event: ping\n\n"]).is_empty());
+    }
+
+    #[test]
+    fn content_and_usage_of_one_chunk_are_both_kept() {
+        let events = decode_all(&[
+            b"data: {\"choices\":[{\"delta\":{\"content\":\"bye\"}}],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":1}}\n\n",
+        ]);
+        assert_eq!(
+            contents(&events),
+            vec![
+                StreamDelta::Content("bye".to_string()),
+                StreamDelta::Usage(Usage::reported(5, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_chunks_are_backend_errors() {
+        let events = decode_all(&[
+            b"data: {\"error\":{\"message\":\"out of memory\",\"type\":\"server_error\"}}\n\n",
+        ]);
+        assert_eq!(
+            StreamDelta::from_openai(&events[0]),
+            Err(LLMError::Backend("out of memory".to_string()))
+        );
+    }
+}
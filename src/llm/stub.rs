@@ -0,0 +1,170 @@
+//! Local HTTP server standing in for a backend in tests, and the profiles
+//! and scratch directories the tests share.
+
+use super::*;
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+
+/// The request received by the stub.
+pub struct StubRequest {
+    /// Request line and headers, header names lowercased by the client.
+    pub head: String,
+    pub body: Value,
+}
+
+/// One canned response.
+pub struct StubResponse {
+    status: &'static str,
+    headers: Vec<(&'static str, &'static str)>,
+    body: &'static str,
+}
+
+impl StubResponse {
+    pub fn new(status: &'static str, body: &'static str) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: &'static str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+/// Answers the next requests on a local port with `responses`, in order.
+/// Returns the base url and a handle resolving to the requests.
+pub async fn serve(responses: Vec<StubResponse>) -> (String, JoinHandle<Vec<StubRequest>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let server = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            requests.push(read_request(&mut socket).await);
+
+            let mut head = format!("HTTP/1.1 {}\r\nConnection: close\r\n", response.status);
+            for (name, value) in response.headers {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            socket
+                .write_all(format!("{}\r\n{}", head, response.body).as_bytes())
+                .await
+                .unwrap();
+        }
+        requests
+    });
+
+    (url, server)
+}
+
+/// Answers the first request with `body`.
+pub async fn serve_once(
+    content_type: &'static str,
+    body: &'static str,
+) -> (String, JoinHandle<StubRequest>) {
+    let (url, server) = serve(vec![
+        StubResponse::new("200 OK", body).header("Content-Type", content_type)
+    ])
+    .await;
+    let server = tokio::spawn(async move { server.await.unwrap().remove(0) });
+    (url, server)
+}
+
+async fn read_request(socket: &mut TcpStream) -> StubRequest {
+    let mut request = Vec::new();
+    let mut buf = [0; 4096];
+    // Read the headers, then the body announced by Content-Length.
+    let (end, length) = loop {
+        let n = socket.read(&mut buf).await.unwrap();
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request).to_lowercase();
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length = text[..end]
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length:"))
+                .and_then(|l| l.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+                break (end, length);
+            }
+        }
+    };
+
+    StubRequest {
+        head: String::from_utf8_lossy(&request[..end]).into_owned(),
+        body: serde_json::from_slice(&request[end + 4..end + 4 + length]).unwrap_or(Value::Null),
+    }
+}
+
+/// Concatenates the streamed answer up to `EndAnswer`.
+pub fn collect_answer(receiver: &mut UnboundedReceiver<Event>) -> String {
+    let mut answer = String::new();
+    while let Ok(event) = receiver.try_recv() {
+        match event {
+            Event::LLMEvent(LLMAnswer::Answer(text)) => answer.push_str(&text),
+            Event::LLMEvent(LLMAnswer::EndAnswer) => break,
+            _ => {}
+        }
+    }
+    answer
+}
+
+/// A profile of `backend` reached at `url`.
+pub fn profile(backend: &str, url: &str) -> Profile {
+    profile_with(backend, &format!("url = \"{}\"", url))
+}
+
+/// A profile of `backend` with the TOML `fields`.
+pub fn profile_with(backend: &str, fields: &str) -> Profile {
+    toml::from_str(&format!(
+        "name = \"{0}\"\nbackend = \"{0}\"\n{1}",
+        backend, fields
+    ))
+    .unwrap()
+}
+
+/// A directory of its own under the system temp dir, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "infini-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Default for TempDir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,385 @@
+//! Built-in local tools the model may call, scoped to `file_explorer_path`.
+//!
+//! Every call is shown in the chat and only runs once the user allows it;
+//! its output, or the refusal, is sent back to the model in a new round.
+
+use super::*;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use regex::Regex;
+use tokio::sync::oneshot;
+
+use crate::utils::{get_git_diff, traverse_directory};
+
+/// Rounds of tool calls in one answer, the answer fails when the model wants more.
+const MAX_TOOL_ROUNDS: usize = 8;
+/// Tool outputs are cut to this many characters before being sent back.
+const MAX_OUTPUT_CHARS: usize = 16_000;
+const MAX_GREP_MATCHES: usize = 200;
+
+const DENIED: &str = "The user denied this tool call.";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+impl ToolCall {
+    /// The call as shown in the chat, e.g. `read_file(path: "src/main.rs")`.
+    pub fn summary(&self) -> String {
+        let arguments = match &self.arguments {
+            Value::Object(arguments) => arguments
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Value::Null => String::new(),
+            other => other.to_string(),
+        };
+        format!("{}({})", self.name, arguments)
+    }
+}
+
+/// A tool call waiting for the user to allow or deny it.
+#[derive(Clone)]
+pub struct ToolRequest {
+    pub call: ToolCall,
+    reply: Arc<std::sync::Mutex<Option<oneshot::Sender<bool>>>>,
+}
+
+impl ToolRequest {
+    pub fn answer(&self, allowed: bool) {
+        if let Some(reply) = self.reply.lock().unwrap().take() {
+            let _ = reply.send(allowed);
+        }
+    }
+}
+
+impl Debug for ToolRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ToolRequest")
+            .field("call", &self.call)
+            .finish()
+    }
+}
+
+/// How the tool turns are written back into the request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToolFormat {
+    /// `tool_calls` with JSON-encoded arguments, results matched by `tool_call_id`.
+    OpenAI,
+    /// `tool_calls` with argument objects, results matched by `tool_name`.
+    Ollama,
+}
+
+/// What one streamed round of an answer produced.
+#[derive(Debug, Default, PartialEq)]
+pub struct Turn {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tools {
+    root: PathBuf,
+}
+
+impl Tools {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The `tools` of the request, in the OpenAI format Ollama also reads.
+    pub fn definitions(&self) -> Value {
+        let path = json!({
+            "type": "string",
+            "description": "Path relative to the workspace root",
+        });
+        json!([
+            function(
+                "read_file",
+                "Read a text file of the workspace.",
+                json!({ "path": path }),
+                &["path"],
+            ),
+            function(
+                "list_dir",
+                "List the files under a directory of the workspace, as a tree. Files ignored by git are left out.",
+                json!({ "path": path }),
+                &[],
+            ),
+            function(
+                "grep",
+                "Search the files of the workspace for a regular expression, returns `path:line: text` matches.",
+                json!({
+                    "pattern": { "type": "string", "description": "Regular expression" },
+                    "path": path,
+                }),
+                &["pattern"],
+            ),
+            function(
+                "git_diff",
+                "Show the changes staged in the git index of the workspace, against HEAD.",
+                json!({}),
+                &[],
+            ),
+        ])
+    }
+
+    /// Shows `call` in the chat and runs it once allowed. Returns what the model gets back.
+    pub async fn call(
+        &self,
+        call: &ToolCall,
+        sender: &UnboundedSender<Event>,
+    ) -> Result<String, LLMError> {
+        let (reply, decision) = oneshot::channel();
+        let request = ToolRequest {
+            call: call.clone(),
+            reply: Arc::new(std::sync::Mutex::new(Some(reply))),
+        };
+        sender.send(Event::LLMEvent(LLMAnswer::ToolCall(request)))?;
+
+        if !decision.await.map_err(|_| LLMError::Cancelled)? {
+            return Ok(DENIED.to_string());
+        }
+
+        // Reading and searching the workspace blocks, off the async workers
+        let tools = self.clone();
+        let (name, arguments) = (call.name.clone(), call.arguments.clone());
+        let output = tokio::task::spawn_blocking(move || tools.run(&name, &arguments))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => format!("Error: {}", e),
+        };
+        Ok(truncate(output))
+    }
+
+    fn run(&self, name: &str, arguments: &Value) -> Result<String, String> {
+        let path = arguments["path"].as_str().unwrap_or(".");
+        match name {
+            "read_file" => {
+                let path = arguments["path"]
+                    .as_str()
+                    .ok_or("`path` is required".to_string())?;
+                fs::read_to_string(self.resolve(path)?).map_err(|e| e.to_string())
+            }
+            "list_dir" => {
+                let (tree, _) = self.traverse(path)?;
+                Ok(tree)
+            }
+            "grep" => {
+                let pattern = arguments["pattern"]
+                    .as_str()
+                    .ok_or("`pattern` is required".to_string())?;
+                self.grep(pattern, path)
+            }
+            "git_diff" => {
+                let diff = get_git_diff(&self.root).map_err(|e| e.to_string())?;
+                Ok(if diff.is_empty() {
+                    "No staged changes.".to_string()
+                } else {
+                    diff
+                })
+            }
+            _ => Err(format!("unknown tool `{}`", name)),
+        }
+    }
+
+    /// `path` inside the workspace, refusing anything that resolves outside of it.
+    fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let root = self.root.canonicalize().map_err(|e| e.to_string())?;
+        let resolved = root
+            .join(path)
+            .canonicalize()
+            .map_err(|e| format!("{}: {}", path, e))?;
+        if resolved.starts_with(&root) {
+            Ok(resolved)
+        } else {
+            Err(format!("`{}` is outside of the workspace", path))
+        }
+    }
+
+    fn traverse(&self, path: &str) -> Result<(String, Vec<Value>), String> {
+        traverse_directory(&self.resolve(path)?, &None, &None, &None, &None, false)
+            .map_err(|e| e.to_string())
+    }
+
+    fn grep(&self, pattern: &str, path: &str) -> Result<String, String> {
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        let root = self.root.canonicalize().map_err(|e| e.to_string())?;
+        let (_, files) = self.traverse(path)?;
+
+        let mut matches = Vec::new();
+        for file in files {
+            let Some(file) = file["path"].as_str().map(PathBuf::from) else {
+                continue;
+            };
+            let Ok(text) = fs::read_to_string(&file) else {
+                continue;
+            };
+            let name = file.strip_prefix(&root).unwrap_or(&file).display();
+            for (i, line) in text.lines().enumerate() {
+                if regex.is_match(line) {
+                    matches.push(format!("{}:{}: {}", name, i + 1, line.trim_end()));
+                    if matches.len() == MAX_GREP_MATCHES {
+                        matches.push("(more matches left out)".to_string());
+                        return Ok(matches.join("\n"));
+                    }
+                }
+            }
+        }
+
+        Ok(if matches.is_empty() {
+            "No matches.".to_string()
+        } else {
+            matches.join("\n")
+        })
+    }
+}
+
+fn function(name: &str, description: &str, properties: Value, required: &[&str]) -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": name,
+            "description": description,
+            "parameters": {
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            },
+        },
+    })
+}
+
+fn truncate(output: String) -> String {
+    match output.char_indices().nth(MAX_OUTPUT_CHARS) {
+        Some((end, _)) => format!(
+            "{}\n(output cut at {} characters)",
+            &output[..end],
+            MAX_OUTPUT_CHARS
+        ),
+        None => output,
+    }
+}
+
+/// The tool calling loop of one answer: writes each round that ended with
+/// tool calls back into the request, with the calls' results.
+pub struct ToolLoop<'a> {
+    tools: &'a Tools,
+    format: ToolFormat,
+    rounds: usize,
+}
+
+impl<'a> ToolLoop<'a> {
+    pub fn new(tools: &'a Tools, format: ToolFormat) -> Self {
+        Self {
+            tools,
+            format,
+            rounds: 0,
+        }
+    }
+
+    /// Appends `turn` and its tool results to `messages`. Returns whether the
+    /// model has to be asked again, an error once it called tools for
+    /// `MAX_TOOL_ROUNDS` rounds.
+    pub async fn feed_back(
+        &mut self,
+        messages: &mut Vec<Value>,
+        turn: Turn,
+        sender: &UnboundedSender<Event>,
+    ) -> Result<bool, LLMError> {
+        if turn.tool_calls.is_empty() {
+            return Ok(false);
+        }
+        if self.rounds == MAX_TOOL_ROUNDS {
+            return Err(LLMError::Backend(format!(
+                "the model still calls tools after {} rounds, the limit of one answer",
+                MAX_TOOL_ROUNDS
+            )));
+        }
+        self.rounds += 1;
+
+        let calls: Vec<Value> = turn
+            .tool_calls
+            .iter()
+            .map(|call| match self.format {
+                ToolFormat::OpenAI => json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": { "name": call.name, "arguments": call.arguments.to_string() },
+                }),
+                ToolFormat::Ollama => json!({
+                    "function": { "name": call.name, "arguments": call.arguments },
+                }),
+            })
+            .collect();
+        messages.push(json!({
+            "role": "assistant",
+            "content": turn.content,
+            "tool_calls": calls,
+        }));
+
+        for call in &turn.tool_calls {
+            let output = self.tools.call(call, sender).await?;
+            messages.push(match self.format {
+                ToolFormat::OpenAI => json!({
+                    "role": "tool",
+                    "tool_call_id": call.id,
+                    "content": output,
+                }),
+                ToolFormat::Ollama => json!({
+                    "role": "tool",
+                    "tool_name": call.name,
+                    "content": output,
+                }),
+            });
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::stub::TempDir;
+
+    #[test]
+    fn tools_stay_in_the_workspace() {
+        let root = TempDir::new();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("src/main.rs"),
+            "fn main() {\n    println!(\"hi\");\n}\n",
+        )
+        .unwrap();
+        let tools = Tools::new(&*root);
+
+        assert_eq!(
+            tools.run("read_file", &json!({ "path": "src/main.rs" })),
+            Ok("fn main() {\n    println!(\"hi\");\n}\n".to_string())
+        );
+        assert_eq!(
+            tools.run("grep", &json!({ "pattern": "print" })),
+            Ok("src/main.rs:2:     println!(\"hi\");".to_string())
+        );
+        assert!(tools
+            .run("list_dir", &json!({}))
+            .unwrap()
+            .contains("main.rs"));
+        assert_eq!(
+            tools.run("read_file", &json!({ "path": "../" })),
+            Err("`../` is outside of the workspace".to_string())
+        );
+    }
+}
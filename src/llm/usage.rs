@@ -0,0 +1,101 @@
+//! Token accounting: counts reported by the backends when they have them,
+//! `cl100k_base` estimates otherwise.
+
+use super::*;
+
+use tiktoken_rs::cl100k_base_singleton;
+
+/// Tokens spent on one answer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Counted locally rather than reported by the backend.
+    pub estimated: bool,
+}
+
+impl Usage {
+    pub fn reported(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            estimated: false,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// Reads an OpenAI-style `usage` object, as sent by OpenAI, ChatGLM and
+    /// most compatible servers.
+    pub fn from_openai(usage: &Value) -> Option<Self> {
+        Some(Self::reported(
+            usage["prompt_tokens"].as_u64()?,
+            usage["completion_tokens"].as_u64()?,
+        ))
+    }
+}
+
+/// Per-message overhead of the chat formats (role and separators).
+pub const TOKENS_PER_MESSAGE: u64 = 4;
+
+pub fn estimate_tokens(text: &str) -> u64 {
+    cl100k_base_singleton()
+        .lock()
+        .encode_with_special_tokens(text)
+        .len() as u64
+}
+
+pub fn estimate_prompt(messages: &[Message]) -> u64 {
+    messages
+        .iter()
+        .map(|m| estimate_tokens(&m.content) + TOKENS_PER_MESSAGE)
+        .sum()
+}
+
+/// Context window of well-known models, used when the profile doesn't set `context_window`.
+pub fn context_limit(model: &str) -> Option<u64> {
+    let model = model.to_lowercase();
+    if model.starts_with("gpt-") {
+        return Some(tiktoken_rs::model::get_context_size(&model) as u64);
+    }
+
+    const LIMITS: &[(&str, u64)] = &[
+        ("claude", 200_000),
+        ("glm-4", 128_000),
+        ("llama3.1", 128_000),
+        ("llama3", 8_192),
+        ("llama2", 4_096),
+        ("codellama", 16_384),
+        ("qwen2", 32_768),
+        ("mistral", 32_768),
+    ];
+    LIMITS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, limit)| *limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_and_limits() {
+        assert_eq!(estimate_tokens("hello world"), 2);
+        assert_eq!(
+            estimate_prompt(&[Message::new(LLMRole::USER, "hello world".to_string())]),
+            2 + TOKENS_PER_MESSAGE
+        );
+        assert_eq!(context_limit("gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_limit("Qwen2-7B-Instruct"), Some(32_768));
+        assert_eq!(context_limit("default"), None);
+        assert_eq!(
+            Usage::from_openai(
+                &json!({"prompt_tokens": 9, "completion_tokens": 12, "total_tokens": 21})
+            ),
+            Some(Usage::reported(9, 12))
+        );
+    }
+}
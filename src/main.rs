@@ -1,36 +1,49 @@
 use clap::crate_version;
+use infini::config::config_dir;
+use infini::event::watch_config;
+use infini::ui::{Notification, NotificationLevel};
 use infini::*;
 use ratatui::backend::CrosstermBackend;
 use ratatui::text::Line;
 use ratatui::Terminal;
 use std::sync::Arc;
 use std::{env, io};
-use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
 
 #[tokio::main]
 async fn main() -> AppResult<()> {
     setup_logging()?;
     let matches = infini::cli().version(crate_version!()).get_matches();
     let config = Arc::new(Config::load());
-    let formatter = setup_formatter(&config)?;
+    let formatter = setup_formatter()?;
 
     let mut app = App::new(config.clone(), &formatter);
 
     // 如果命令行指定了语言，就使用命令行的，否则使用配置文件的
-    let lang = matches.get_one::<String>("lang").unwrap_or(&config.language);
+    let lang = matches
+        .get_one::<String>("lang")
+        .unwrap_or(&config.language);
     set_language(lang);
 
-    let llm = Arc::new(Mutex::new(
-        LLMModel::init(&config.llm, config.clone()).await,
-    ));
+    let profile = config.default_profile();
+    let llm = match LLMModel::init(profile, &config).await {
+        Ok(llm) => llm,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    app.set_llm(&profile.name, llm.as_ref());
+    let mut llm: Arc<dyn LLM> = Arc::from(llm);
 
     let backend = CrosstermBackend::new(io::stderr());
     let terminal = Terminal::new(backend)?;
     let events = EventHandler::new(250);
     let mut tui = Tui::new(terminal, events);
     tui.init()?;
+    watch_config(config_dir(), tui.events.sender.clone());
 
-    main_loop(&mut app, &llm, &mut tui, &formatter).await?;
+    main_loop(&mut app, &mut llm, &mut tui, &formatter).await?;
 
     tui.exit()?;
     Ok(())
@@ -47,7 +60,7 @@ fn setup_logging() -> AppResult<()> {
     Ok(())
 }
 
-fn setup_formatter(config: &Arc<Config>) -> AppResult<Formatter> {
+fn setup_formatter() -> AppResult<Formatter<'static>> {
     let (formatter_config, formatter_assets) = Formatter::init();
     let formatter = Formatter::new(
         Box::leak(Box::new(formatter_config)),
@@ -58,7 +71,7 @@ fn setup_formatter(config: &Arc<Config>) -> AppResult<Formatter> {
 
 async fn main_loop(
     app: &mut App<'_>,
-    llm: &Arc<Mutex<Box<dyn LLM>>>,
+    llm: &mut Arc<dyn LLM>,
     tui: &mut Tui<CrosstermBackend<std::io::Stderr>>,
     formatter: &Formatter<'_>,
 ) -> AppResult<()> {
@@ -67,48 +80,93 @@ async fn main_loop(
         match tui.events.next().await? {
             Event::Tick => app.tick(),
             Event::Key(key_event) => {
-                handle_key_events(key_event, app, llm.clone(), tui.events.sender.clone()).await?;
+                handle_key_events(key_event, app, llm, tui.events.sender.clone()).await?;
             }
             Event::Mouse(_) => {}
             Event::Resize(_, _) => {}
             Event::LLMEvent(llm_event) => {
-                handle_llm_event(llm_event, app, llm.clone(), formatter).await?
+                handle_llm_event(llm_event, app, llm, &tui.events.sender, formatter)
+            }
+            Event::Answer(id, llm_event) => {
+                if id == app.answer_id {
+                    handle_llm_event(llm_event, app, llm, &tui.events.sender, formatter)
+                }
             }
+            Event::Models(event) => app.model_picker.handle(event),
             Event::Notification(notification) => {
                 app.notifications.push(notification);
             }
+            Event::Config(config) => apply_config(app, llm, config, &tui.events.sender).await,
         }
     }
     Ok(())
 }
 
-async fn handle_llm_event(
+fn handle_llm_event(
     llm_event: LLMAnswer,
     app: &mut App<'_>,
-    llm: Arc<Mutex<Box<dyn LLM>>>,
+    llm: &Arc<dyn LLM>,
+    sender: &UnboundedSender<Event>,
     formatter: &Formatter<'_>,
-) -> AppResult<()> {
+) {
     match llm_event {
         LLMAnswer::Answer(answer) => {
             app.chat.handle_answer(LLMAnswer::Answer(answer), formatter);
         }
         LLMAnswer::EndAnswer => {
-            {
-                let mut llm = llm.lock().await;
-                llm.append_chat_msg(app.chat.answer.plain_answer.clone(), LLMRole::ASSISTANT);
-            }
             app.chat.handle_answer(LLMAnswer::EndAnswer, formatter);
-            app.terminate_response_signal
-                .store(false, std::sync::atomic::Ordering::Relaxed);
+            app.pending_tool = None;
             app.chat
                 .formatted_chat
                 .lines
-                .push(Line::raw(format!("🤖: End of Answer.")));
+                .push(Line::raw("🤖: End of Answer."));
+            app.answering = false;
+            send_queued(app, llm, sender);
+        }
+        LLMAnswer::Timings(_)
+        | LLMAnswer::Usage(_)
+        | LLMAnswer::ContextTrimmed(_)
+        | LLMAnswer::PromptEstimate(_) => {
+            app.chat.handle_answer(llm_event, formatter);
+        }
+        LLMAnswer::ToolCall(request) => {
+            app.chat
+                .handle_answer(LLMAnswer::ToolCall(request.clone()), formatter);
+            app.pending_tool = Some(request);
+        }
+        LLMAnswer::Retrying(retry) => {
+            app.notifications.push(Notification::new(
+                retry.to_string(),
+                NotificationLevel::Warning,
+            ));
+        }
+        LLMAnswer::ImagesLeftOut(count) => {
+            app.notifications.push(Notification::new(
+                format!(
+                    "The {} backend can not read images, {} of earlier messages were left out",
+                    llm.backend(),
+                    count
+                ),
+                NotificationLevel::Info,
+            ));
         }
         LLMAnswer::StartAnswer => {
             app.spinner.active = false;
             app.chat.handle_answer(LLMAnswer::StartAnswer, formatter);
         }
+        LLMAnswer::Error(error) => {
+            app.chat
+                .handle_answer(LLMAnswer::Error(error.clone()), formatter);
+            app.spinner.active = false;
+            app.pending_tool = None;
+            if error != LLMError::Cancelled {
+                app.notifications.push(Notification::new(
+                    error.to_string(),
+                    NotificationLevel::Error,
+                ));
+            }
+            app.answering = false;
+            send_queued(app, llm, sender);
+        }
     }
-    Ok(())
 }
@@ -0,0 +1,85 @@
+use super::*;
+
+use crate::config::Profile;
+
+/// Popup listing the configured profiles, to switch the active backend at runtime.
+#[derive(Debug, Default, Clone)]
+pub struct BackendPicker {
+    state: ListState,
+    pub profiles: Vec<Profile>,
+}
+
+impl BackendPicker {
+    pub fn new(profiles: Vec<Profile>) -> Self {
+        Self {
+            state: ListState::default(),
+            profiles,
+        }
+    }
+
+    /// Resets the selection to the active profile before the popup is shown.
+    pub fn open(&mut self, active: &str) {
+        let selected = self.profiles.iter().position(|p| p.name == active);
+        self.state.select(selected.or(Some(0)));
+    }
+
+    /// Name of the highlighted profile.
+    pub fn selected(&self) -> Option<String> {
+        self.state
+            .selected()
+            .and_then(|i| self.profiles.get(i))
+            .map(|p| p.name.clone())
+    }
+
+    pub fn scroll_down(&mut self) {
+        if self.profiles.is_empty() {
+            return;
+        }
+        let i = match self.state.selected() {
+            Some(i) if i < self.profiles.len() - 1 => i + 1,
+            Some(i) => i,
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    pub fn scroll_up(&mut self) {
+        let i = match self.state.selected() {
+            Some(i) => i.saturating_sub(1),
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    pub fn render(&mut self, frame: &mut Frame, area: Rect, active: &str) {
+        let items = self
+            .profiles
+            .iter()
+            .map(|profile| {
+                let marker = if profile.name == active { "●" } else { " " };
+                ListItem::new(format!(
+                    "{} {}  {} · {}",
+                    marker,
+                    profile.name,
+                    profile.backend,
+                    profile.model.as_deref().unwrap_or("default")
+                ))
+            })
+            .collect::<Vec<ListItem>>();
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" {} ", t!("backend_picker_title")))
+                    .title_style(Style::default().bold())
+                    .title_alignment(Alignment::Center)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Green)),
+            )
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.state);
+    }
+}
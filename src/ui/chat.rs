@@ -1,67 +1,90 @@
 use super::*;
 
 use super::spinner::Spinner;
-use crate::llm::LLMAnswer;
-use futures::executor::block_on;
-use std::{
-    rc::Rc,
-    sync::atomic::{AtomicBool, Ordering},
-};
-use tokio::time::{self, Duration};
-use tracing::info; // 引用 Spinner 模块
+use crate::llm::preset::DEFAULT_PRESET;
+use crate::llm::tools::ToolCall;
+use crate::llm::usage::estimate_tokens;
+use crate::llm::{LLMAnswer, LLMError, LLMRole, Message, Timings, Usage};
+use std::{rc::Rc, sync::atomic::AtomicBool};
+use tokio::time::{self, Duration}; // 引用 Spinner 模块
 
 use super::formatter::Formatter;
+use super::queue::PromptQueue;
 
-use ratatui::prelude::*;
 use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
 
 use rust_i18n::t;
 
 #[derive(Debug, Clone, Default)]
 pub struct Answer<'a> {
+    /// Set once the backend started answering, which replaces the `🤖: ` placeholder.
+    pub started: bool,
     pub plain_answer: String,
+    /// Bytes of `plain_answer` already moved into the chat, above a tool call.
+    pub shown: usize,
     pub formatted_answer: Text<'a>,
+    pub timings: Option<Timings>,
+    pub usage: Option<Usage>,
+    /// Estimated tokens of the request, for backends that report no usage.
+    pub prompt_estimate: u64,
+    /// Older messages left out of the request.
+    pub left_out: usize,
 }
 
-#[derive(Debug, Clone)]
-pub struct ChatMessage {
-    // pub sender: String,
-    pub content: String,
-    pub style: Style,
+/// Plain text form of a message, as written to the archive file.
+pub fn plain_message(message: &Message) -> String {
+    match message.role {
+        LLMRole::USER => {
+            let images: String = message
+                .attachments
+                .iter()
+                .map(|image| format!("{}\n", image.label()))
+                .collect();
+            format!("👤 : {}\n{}", message.content, images)
+        }
+        _ if message.stopped => format!("🤖: {} [stopped]", message.content),
+        _ => format!("🤖: {}", message.content),
+    }
 }
 
-impl ChatMessage {
-    pub fn new(content: String, is_user: bool) -> Self {
-        let style = if is_user {
-            Style::default().fg(Color::Blue)
-        } else {
-            Style::default().fg(Color::Green)
-        };
-        Self { content, style }
-    }
+/// Plain text form of a whole conversation.
+pub fn transcript(messages: &[Message]) -> String {
+    messages.iter().map(plain_message).collect()
 }
 
 #[derive(Debug, Clone)]
 pub struct Chat<'a> {
-    pub plain_chat: Vec<String>,
+    pub messages: Vec<Message>,
+    /// Prompt preset of the conversation.
+    pub preset: String,
+    /// Tokens spent on the last answer.
+    pub usage: Option<Usage>,
     pub formatted_chat: Text<'a>,
     pub answer: Answer<'a>,
+    /// Answers given to the last prompt, `sibling` is the one in `messages`.
+    pub siblings: Vec<Message>,
+    pub sibling: usize,
+    /// First line of the last answer in `formatted_chat`.
+    answer_start: usize,
     pub scroll: u16,
     area_height: u16,
     area_width: u16,
     pub automatic_scroll: Rc<AtomicBool>,
     pub ai_typing: bool,
     pub spinner: Spinner, // 使用 Spinner
-    // pub messages: Vec<ChatMessage>,
-
 }
 
 impl Default for Chat<'_> {
     fn default() -> Self {
         Self {
-            plain_chat: Vec::new(),
+            messages: Vec::new(),
+            preset: DEFAULT_PRESET.to_string(),
+            usage: None,
             formatted_chat: Text::raw(""),
             answer: Answer::default(),
+            siblings: Vec::new(),
+            sibling: 0,
+            answer_start: 0,
             scroll: 0,
             area_height: 0,
             area_width: 0,
@@ -73,39 +96,233 @@ impl Default for Chat<'_> {
 }
 
 impl Chat<'_> {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(preset: &str) -> Self {
+        Self {
+            preset: preset.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the prompt of a new turn, the answers of the previous one are settled.
+    pub fn push_prompt(&mut self, message: Message) {
+        self.siblings.clear();
+        self.sibling = 0;
+        self.messages.push(message);
+    }
+
+    /// Shows the `🤖: ` placeholder of the answer about to be streamed.
+    pub fn await_answer(&mut self) {
+        self.answer_start = self.formatted_chat.lines.len();
+        self.formatted_chat
+            .lines
+            .push(Line::raw("🤖: ".to_string()));
+    }
+
+    /// Takes the last answer out of the conversation, so it can be asked
+    /// again. It stays among the siblings. A prompt whose answer failed is
+    /// asked again as is.
+    pub fn regenerate(&mut self) -> bool {
+        match self.messages.last().map(|m| m.role) {
+            Some(LLMRole::ASSISTANT) => {
+                let answer = self.messages.pop().unwrap();
+                if self.siblings.is_empty() {
+                    self.siblings.push(answer);
+                    self.sibling = 0;
+                } else {
+                    self.siblings[self.sibling] = answer;
+                }
+            }
+            Some(LLMRole::USER) => {}
+            _ => return false,
+        }
+        self.formatted_chat.lines.truncate(self.answer_start);
+        true
+    }
+
+    /// Shows the next (or previous) sibling of the last answer, which is then
+    /// the one follow-ups are based on.
+    pub fn cycle_sibling(&mut self, forward: bool, formatter: &Formatter) -> bool {
+        let count = self.siblings.len();
+        if count < 2 || self.messages.last().map(|m| m.role) != Some(LLMRole::ASSISTANT) {
+            return false;
+        }
+        self.siblings[self.sibling] = self.messages.pop().unwrap();
+        self.sibling = if forward {
+            (self.sibling + 1) % count
+        } else {
+            (self.sibling + count - 1) % count
+        };
+        self.show_sibling(formatter);
+        true
+    }
+
+    /// Puts the selected sibling back in place of the last answer.
+    fn show_sibling(&mut self, formatter: &Formatter) {
+        let answer = self.siblings[self.sibling].clone();
+        self.formatted_chat.lines.truncate(self.answer_start);
+        self.formatted_chat
+            .extend(formatter.format(&format!("🤖: {}", answer.content)));
+        self.push_sibling_line();
+        self.formatted_chat.extend(Text::raw("\n"));
+        self.messages.push(answer);
+    }
+
+    fn push_sibling_line(&mut self) {
+        if self.siblings.len() > 1 {
+            self.formatted_chat.lines.push(Line::styled(
+                t!(
+                    "answer_siblings",
+                    index = self.sibling + 1,
+                    count = self.siblings.len()
+                )
+                .to_string(),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
     }
 
     pub fn handle_answer(&mut self, event: LLMAnswer, formatter: &Formatter) {
         match event {
             LLMAnswer::StartAnswer => {
                 self.formatted_chat.lines.pop();
+                self.answer.started = true;
             }
 
             LLMAnswer::Answer(answer) => {
                 self.answer.plain_answer.push_str(answer.as_str());
-                self.answer.formatted_answer =
-                    formatter.format(format!("🤖: {}", &self.answer.plain_answer).as_str());
+                self.answer.formatted_answer = formatter.format(
+                    format!("🤖: {}", &self.answer.plain_answer[self.answer.shown..]).as_str(),
+                );
             }
 
-            LLMAnswer::EndAnswer => {
-                self.formatted_chat
-                    .extend(self.answer.formatted_answer.clone());
+            LLMAnswer::Timings(timings) => {
+                self.answer.timings = Some(timings);
+            }
 
-                self.formatted_chat.extend(Text::raw("\n"));
+            LLMAnswer::Usage(usage) => {
+                self.answer.usage = Some(usage);
+            }
 
-                self.plain_chat
-                    .push(format!("🤖: {}", self.answer.plain_answer));
+            LLMAnswer::ContextTrimmed(left_out) => {
+                self.answer.left_out = left_out;
+            }
+
+            LLMAnswer::PromptEstimate(tokens) => {
+                self.answer.prompt_estimate = tokens;
+            }
+
+            LLMAnswer::EndAnswer => self.end_answer(),
+
+            // Shown as a notification, the placeholder stays
+            LLMAnswer::Retrying(_) | LLMAnswer::ImagesLeftOut(_) => {}
+
+            LLMAnswer::ToolCall(request) => {
+                // The answer so far stays above the call, the rest streams below it
+                if self.answer.shown < self.answer.plain_answer.len() {
+                    self.formatted_chat
+                        .extend(std::mem::take(&mut self.answer.formatted_answer));
+                    self.answer.shown = self.answer.plain_answer.len();
+                }
+                self.formatted_chat.lines.push(Line::styled(
+                    t!("tool_call_pending", call = request.call.summary()).to_string(),
+                    Style::default().fg(Color::Yellow),
+                ));
+            }
 
+            LLMAnswer::Error(error) => {
+                if !self.answer.started {
+                    self.formatted_chat.lines.pop();
+                }
+                let stopped = error == LLMError::Cancelled;
+                // Keep what was streamed before the failure
+                if !self.answer.plain_answer.is_empty() {
+                    self.end_answer();
+                    if let Some(message) = self.messages.last_mut() {
+                        message.stopped = stopped;
+                    }
+                } else if !self.siblings.is_empty() {
+                    // Nothing new, the answer asked again stays
+                    self.show_sibling(formatter);
+                }
+                self.formatted_chat.lines.push(if stopped {
+                    Line::styled(
+                        t!("answer_stopped").to_string(),
+                        Style::default().fg(Color::DarkGray),
+                    )
+                } else {
+                    Line::styled(
+                        t!("answer_failed").to_string(),
+                        Style::default().fg(Color::Red),
+                    )
+                });
                 self.answer = Answer::default();
             }
         }
     }
 
-    pub fn height(&self) -> usize {
-        let mut chat = self.formatted_chat.clone();
-        chat.extend(self.answer.formatted_answer.clone());
+    fn end_answer(&mut self) {
+        if self.answer.left_out > 0 {
+            self.formatted_chat.lines.push(Line::styled(
+                t!("context_trimmed", count = self.answer.left_out).to_string(),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+
+        self.formatted_chat
+            .extend(self.answer.formatted_answer.clone());
+
+        if let Some(timings) = &self.answer.timings {
+            self.formatted_chat.lines.push(Line::styled(
+                format!("⏱ {}", timings),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+
+        let usage = self.answer.usage.unwrap_or_else(|| Usage {
+            prompt_tokens: self.answer.prompt_estimate,
+            completion_tokens: estimate_tokens(&self.answer.plain_answer),
+            estimated: true,
+        });
+        self.usage = Some(usage);
+
+        let mut message = Message::new(LLMRole::ASSISTANT, self.answer.plain_answer.clone());
+        message.tokens = Some(usage.completion_tokens);
+
+        // An answer asked again joins the earlier ones
+        if !self.siblings.is_empty() {
+            self.siblings.push(message.clone());
+            self.sibling = self.siblings.len() - 1;
+            self.push_sibling_line();
+        }
+        self.formatted_chat.extend(Text::raw("\n"));
+        self.messages.push(message);
+
+        self.answer = Answer::default();
+    }
+
+    /// Replaces the pending line of `call` with the user's decision.
+    pub fn tool_answered(&mut self, call: &ToolCall, allowed: bool) {
+        self.formatted_chat.lines.pop();
+        let line = if allowed {
+            Line::styled(
+                t!("tool_call_allowed", call = call.summary()).to_string(),
+                Style::default().fg(Color::Green),
+            )
+        } else {
+            Line::styled(
+                t!("tool_call_denied", call = call.summary()).to_string(),
+                Style::default().fg(Color::Red),
+            )
+        };
+        self.formatted_chat.lines.push(line);
+    }
+
+    /// Tokens of every message of the conversation.
+    pub fn tokens(&self) -> u64 {
+        self.messages.iter().filter_map(|m| m.tokens).sum()
+    }
+
+    fn height(&self, chat: &Text) -> usize {
         let nb_lines = chat.lines.len() + 3;
         chat.lines.iter().fold(nb_lines, |acc, line| {
             acc + line.width() / self.area_width as usize
@@ -128,9 +345,10 @@ impl Chat<'_> {
         }
     }
 
-    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+    pub fn render(&mut self, frame: &mut Frame, area: Rect, queue: &PromptQueue) {
         let mut text = self.formatted_chat.clone();
         text.extend(self.answer.formatted_answer.clone());
+        text.extend(queue.chat_lines());
 
         let styled_lines: Vec<Line> = text
             .lines
@@ -153,7 +371,10 @@ impl Chat<'_> {
                                 Span::styled(span.content.clone(), span.style)
                             } else {
                                 // 非代码文本使用绿色
-                                Span::styled(span.content.clone(), Style::default().fg(Color::Green))
+                                Span::styled(
+                                    span.content.clone(),
+                                    Style::default().fg(Color::Green),
+                                )
                             }
                         } else {
                             // 其他情况，保持原样
@@ -175,7 +396,9 @@ impl Chat<'_> {
             .automatic_scroll
             .load(std::sync::atomic::Ordering::Relaxed)
         {
-            let scroll = self.height().saturating_sub(self.area_height.into()) as u16;
+            let scroll = self
+                .height(&styled_text)
+                .saturating_sub(self.area_height.into()) as u16;
             self.scroll = scroll;
             scroll
         } else {
@@ -190,10 +413,86 @@ impl Chat<'_> {
                     .title(t!("ai_chat_title").into_owned())
                     .borders(Borders::ALL)
                     .border_style(Style::default().fg(Color::Green))
-                    .border_type(BorderType::Rounded)
+                    .border_type(BorderType::Rounded),
             )
             .alignment(Alignment::Left);
 
         frame.render_widget(chat, area);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(chat: &mut Chat, text: &str, formatter: &Formatter) {
+        chat.await_answer();
+        chat.handle_answer(LLMAnswer::StartAnswer, formatter);
+        chat.handle_answer(LLMAnswer::Answer(text.to_string()), formatter);
+        chat.handle_answer(
+            LLMAnswer::Usage(Usage {
+                prompt_tokens: 10,
+                completion_tokens: 1,
+                estimated: false,
+            }),
+            formatter,
+        );
+        chat.handle_answer(LLMAnswer::EndAnswer, formatter);
+    }
+
+    fn last<'a>(chat: &'a Chat) -> &'a str {
+        chat.messages.last().unwrap().content.as_str()
+    }
+
+    fn shown(chat: &Chat) -> String {
+        chat.formatted_chat
+            .lines
+            .iter()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn regenerated_answers_are_kept_as_siblings() {
+        let (config, assets) = Formatter::init();
+        let formatter = Formatter::new(&config, &assets);
+        let mut chat = Chat::new(DEFAULT_PRESET);
+        assert!(!chat.regenerate());
+
+        chat.push_prompt(Message::new(LLMRole::USER, "hi".to_string()));
+        answer(&mut chat, "first", &formatter);
+        assert!(chat.siblings.is_empty());
+
+        assert!(chat.regenerate());
+        assert_eq!(last(&chat), "hi");
+        answer(&mut chat, "second", &formatter);
+        assert_eq!((chat.siblings.len(), chat.sibling), (2, 1));
+        assert_eq!(last(&chat), "second");
+        assert!(shown(&chat).contains("2/2"));
+
+        // The shown sibling is the one in the conversation
+        assert!(chat.cycle_sibling(false, &formatter));
+        assert_eq!(last(&chat), "first");
+        assert_eq!(chat.messages.len(), 2);
+        assert!(shown(&chat).contains("1/2"));
+        assert!(!shown(&chat).contains("second"));
+        assert!(chat.cycle_sibling(false, &formatter));
+        assert_eq!(last(&chat), "second");
+
+        // A failed attempt leaves the previous answer in place
+        assert!(chat.regenerate());
+        chat.await_answer();
+        chat.handle_answer(LLMAnswer::Error(LLMError::Timeout), &formatter);
+        assert_eq!(last(&chat), "second");
+        assert_eq!(chat.siblings.len(), 2);
+
+        // A follow-up settles the turn
+        chat.push_prompt(Message::new(LLMRole::USER, "thanks".to_string()));
+        answer(&mut chat, "welcome", &formatter);
+        assert!(chat.siblings.is_empty());
+        assert!(!chat.cycle_sibling(true, &formatter));
+        let contents: Vec<&str> = chat.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["hi", "second", "thanks", "welcome"]);
+    }
+}
@@ -58,7 +58,7 @@ impl FileExplorer {
             cwd,
             files: vec![],
             selected: 0,
-            theme: theme,
+            theme,
             preview: FileExplorerPreview::default(),
         };
 
@@ -262,7 +262,7 @@ impl Theme {
 
     /// Returns the generated top titles of the theme.
     #[inline]
-    pub fn title_top(&self, file_explorer: &FileExplorer) -> Vec<Line> {
+    pub fn title_top(&self, file_explorer: &FileExplorer) -> Vec<Line<'_>> {
         self.title_top
             .iter()
             .map(|title_top| title_top(file_explorer))
@@ -271,7 +271,7 @@ impl Theme {
 
     /// Returns the generated bottom titles of the theme.
     #[inline]
-    pub fn title_bottom(&self, file_explorer: &FileExplorer) -> Vec<Line> {
+    pub fn title_bottom(&self, file_explorer: &FileExplorer) -> Vec<Line<'_>> {
         self.title_bottom
             .iter()
             .map(|title_bottom| title_bottom(file_explorer))
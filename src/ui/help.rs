@@ -25,18 +25,17 @@ impl Default for Help {
             keys: vec![
                 ("Esc", t!("help_esc")),
                 ("Tab", t!("help_tab")),
-                (
-                    "ctrl + n",
-                    t!("help_ctrl_n"),
-                ),
-                (
-                    "ctrl + s",
-                    t!("help_ctrl_s"),
-                ),
+                ("ctrl + n", t!("help_ctrl_n")),
+                ("ctrl + s", t!("help_ctrl_s")),
                 ("ctrl + f", t!("help_ctrl_f")),
                 ("ctrl + p", t!("help_ctrl_p")),
+                ("ctrl + a", t!("help_ctrl_a")),
                 ("ctrl + h", t!("help_ctrl_h")),
+                ("ctrl + b", t!("help_ctrl_b")),
+                ("ctrl + o", t!("help_ctrl_o")),
+                ("ctrl + u", t!("help_ctrl_u")),
                 ("ctrl + t", t!("help_ctrl_t")),
+                ("ctrl + r", t!("help_ctrl_r")),
                 ("j or Down", t!("help_j_or_down")),
                 ("k or Up", t!("help_k_or_up")),
                 ("G", t!("help_g")),
@@ -68,13 +67,7 @@ impl Help {
     }
     pub fn scroll_up(&mut self) {
         let i = match self.state.selected() {
-            Some(i) => {
-                if i > 1 {
-                    i - 1
-                } else {
-                    0
-                }
-            }
+            Some(i) => i.saturating_sub(1),
             None => 1,
         };
         *self.state.offset_mut() = i;
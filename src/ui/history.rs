@@ -1,10 +1,11 @@
 use super::*;
 
+use super::chat::{plain_message, transcript};
 use super::notification::{Notification, NotificationLevel};
 
 use tokio::sync::mpsc::UnboundedSender;
 
-use crate::{app::FocusedBlock, event::Event};
+use crate::{app::FocusedBlock, event::Event, llm::Message};
 
 #[derive(Debug, Default, Clone)]
 pub struct Preview<'a> {
@@ -16,7 +17,9 @@ pub struct Preview<'a> {
 pub struct History<'a> {
     block_height: usize,
     state: ListState,
-    pub text: Vec<Vec<String>>,
+    pub text: Vec<Vec<Message>>,
+    /// Prompt preset of each conversation of `text`.
+    pub presets: Vec<String>,
     pub preview: Preview<'a>,
 }
 
@@ -26,6 +29,7 @@ impl History<'_> {
             block_height: 0,
             state: ListState::default(),
             text: Vec::new(),
+            presets: Vec::new(),
             preview: Preview::default(),
         }
     }
@@ -47,13 +51,8 @@ impl History<'_> {
             return;
         }
         let i = match self.state.selected() {
-            Some(i) => {
-                if i < self.text.len() - 1 {
-                    i + 1
-                } else {
-                    i
-                }
-            }
+            Some(i) if i < self.text.len() - 1 => i + 1,
+            Some(i) => i,
             None => 0,
         };
         self.state.select(Some(i));
@@ -63,13 +62,7 @@ impl History<'_> {
             return;
         }
         let i = match self.state.selected() {
-            Some(i) => {
-                if i > 1 {
-                    i - 1
-                } else {
-                    0
-                }
-            }
+            Some(i) => i.saturating_sub(1),
             None => 0,
         };
         self.state.select(Some(i));
@@ -79,7 +72,7 @@ impl History<'_> {
         if !self.text.is_empty() {
             match std::fs::write(
                 archive_file_name,
-                self.text[self.state.selected().unwrap_or(0)].join(""),
+                transcript(&self.text[self.state.selected().unwrap_or(0)]),
             ) {
                 Ok(_) => {
                     let notif = Notification::new(
@@ -117,9 +110,10 @@ impl History<'_> {
         let items = self
             .text
             .iter()
-            .map(|chat| match chat.first() {
-                Some(v) => ListItem::new(v.to_owned()),
-                None => ListItem::new(""),
+            .zip(&self.presets)
+            .map(|(chat, preset)| match chat.first() {
+                Some(v) => ListItem::new(format!("[{}] {}", preset, plain_message(v))),
+                None => ListItem::new(format!("[{}]", preset)),
             })
             .collect::<Vec<ListItem>>();
 
@@ -1,6 +1,5 @@
 use std;
 
-use ratatui::prelude::*;
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style, Stylize},
@@ -13,22 +12,30 @@ use rust_i18n::t;
 
 use crate::app::{App, FocusedBlock};
 
+pub mod backend_picker;
 pub mod chat;
 pub mod file_explore;
 pub mod formatter;
 pub mod help;
 pub mod history;
+pub mod model_picker;
 pub mod notification;
+pub mod preset_picker;
 pub mod prompt;
+pub mod queue;
 pub mod spinner;
 pub mod tui;
 
+pub use backend_picker::BackendPicker;
 pub use chat::Chat;
 pub use formatter::Formatter;
 pub use help::Help;
 pub use history::{History, Preview};
+pub use model_picker::ModelPicker;
 pub use notification::{Notification, NotificationLevel};
+pub use preset_picker::PresetPicker;
 pub use prompt::Prompt;
+pub use queue::PromptQueue;
 pub use spinner::Spinner;
 pub use tui::Tui;
 
@@ -116,39 +123,65 @@ pub fn render(app: &mut App, frame: &mut Frame) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(1),  // 标题栏
-            Constraint::Min(1),     // 聊天区域
-            Constraint::Length(3),  // 输入框
-            Constraint::Length(1),  // 状态栏
+            Constraint::Length(1), // 标题栏
+            Constraint::Min(1),    // 聊天区域
+            Constraint::Length(3), // 输入框
+            Constraint::Length(1), // 状态栏
         ])
         .split(frame.size());
 
     // 渲染标题栏
-    render_title_bar(frame, chunks[0]);
+    render_title_bar(frame, chunks[0], &app.llm_label(), &app.chat.preset);
 
     // 渲染聊天区域
-    // 渲染聊天区域
-        app.chat.render(frame, chunks[1]);
+    app.chat.render(frame, chunks[1], &app.queue);
 
     // 渲染输入框
     app.prompt.render(frame, chunks[2]);
 
     // 渲染状态栏
-    render_status_bar(frame, chunks[3]);
+    render_status_bar(frame, chunks[3], app);
 
     // 渲染其他弹出窗口 (如果需要)
     render_popups(app, frame);
 }
 
-fn render_title_bar(frame: &mut Frame, area: Rect) {
-    let title = Paragraph::new(t!("ai_chat_title"))
-        .style(Style::default().fg(Color::White))
-        .alignment(Alignment::Center);
+fn render_title_bar(frame: &mut Frame, area: Rect, llm_name: &str, preset: &str) {
+    let title = Paragraph::new(format!(
+        "{} · {} · {}",
+        t!("ai_chat_title"),
+        llm_name,
+        t!("preset_label", name = preset)
+    ))
+    .style(Style::default().fg(Color::White))
+    .alignment(Alignment::Center);
     frame.render_widget(title, area);
 }
 
-fn render_status_bar(frame: &mut Frame, area: Rect) {
-    let status = Paragraph::new(t!("status_area"))
+fn render_status_bar(frame: &mut Frame, area: Rect, app: &App) {
+    let conversation = app.chat.tokens();
+    let mut status = match app.context_limit {
+        Some(limit) => t!(
+            "status_tokens_limit",
+            used = conversation,
+            limit = limit,
+            percent = conversation * 100 / limit.max(1)
+        )
+        .to_string(),
+        None => t!("status_tokens", used = conversation).to_string(),
+    };
+    if let Some(usage) = app.chat.usage {
+        status.push_str(&t!(
+            "status_last_answer",
+            prompt = usage.prompt_tokens,
+            completion = usage.completion_tokens
+        ));
+        if usage.estimated {
+            status.push_str(&t!("status_estimated"));
+        }
+    }
+
+    let status = Paragraph::new(status)
         .style(Style::default().fg(Color::White))
         .alignment(Alignment::Left);
     frame.render_widget(status, area);
@@ -166,7 +199,31 @@ fn render_popups(app: &mut App, frame: &mut Frame) {
     if let FocusedBlock::FileExplorer | FocusedBlock::FileExplorerPreview = app.focused_block {
         let area = centered_rect(80, 80, frame_size);
         app.file_explorer
-            .render(frame, area, app.focused_block.clone(), &app.formatter);
+            .render(frame, area, app.focused_block.clone(), app.formatter);
+    }
+
+    // Backend picker
+    if let FocusedBlock::BackendPicker = app.focused_block {
+        let area = centered_rect(40, 40, frame_size);
+        app.backend_picker.render(frame, area, &app.llm_profile);
+    }
+
+    // Model picker
+    if let FocusedBlock::ModelPicker = app.focused_block {
+        let area = centered_rect(60, 40, frame_size);
+        app.model_picker.render(frame, area, &app.llm_model);
+    }
+
+    // Preset picker
+    if let FocusedBlock::PresetPicker = app.focused_block {
+        let area = centered_rect(40, 40, frame_size);
+        app.preset_picker.render(frame, area, &app.chat.preset);
+    }
+
+    // Prompt queue
+    if let FocusedBlock::Queue = app.focused_block {
+        let area = centered_rect(60, 40, frame_size);
+        app.queue.render(frame, area);
     }
 
     // Help
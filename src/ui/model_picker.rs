@@ -0,0 +1,111 @@
+use super::*;
+
+use crate::llm::attachment::human_size;
+use crate::llm::ollama::{ModelEvent, ModelInfo, PullProgress};
+
+/// Popup listing the models of the active Ollama server, to switch, pull or unload them.
+#[derive(Debug, Default, Clone)]
+pub struct ModelPicker {
+    state: ListState,
+    pub models: Vec<ModelInfo>,
+    /// The list is being fetched.
+    pub loading: bool,
+    /// Progress of the running pull.
+    pub pull: Option<PullProgress>,
+}
+
+impl ModelPicker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows the popup while the list is refreshed.
+    pub fn open(&mut self) {
+        self.loading = true;
+        self.state.select((!self.models.is_empty()).then_some(0));
+    }
+
+    pub fn handle(&mut self, event: ModelEvent) {
+        match event {
+            ModelEvent::Listed(models) => {
+                self.models = models;
+                self.loading = false;
+                let selected = self.state.selected().unwrap_or_default();
+                self.state
+                    .select((!self.models.is_empty()).then(|| selected.min(self.models.len() - 1)));
+            }
+            ModelEvent::Pull(progress) => self.pull = progress,
+        }
+    }
+
+    pub fn selected(&self) -> Option<&ModelInfo> {
+        self.state.selected().and_then(|i| self.models.get(i))
+    }
+
+    pub fn scroll_down(&mut self) {
+        if self.models.is_empty() {
+            return;
+        }
+        let i = match self.state.selected() {
+            Some(i) if i < self.models.len() - 1 => i + 1,
+            Some(i) => i,
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    pub fn scroll_up(&mut self) {
+        let i = match self.state.selected() {
+            Some(i) => i.saturating_sub(1),
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    pub fn render(&mut self, frame: &mut Frame, area: Rect, active: &str) {
+        let items = self
+            .models
+            .iter()
+            .map(|model| {
+                let marker = if model.name == active { "●" } else { " " };
+                let mut details = vec![human_size(model.size)];
+                details.extend(model.parameter_size.clone());
+                details.extend(model.quantization.clone());
+                if let Some(context_length) = model.context_length {
+                    details.push(format!("ctx {}", context_length));
+                }
+                if model.loaded {
+                    details.push(t!("model_loaded").to_string());
+                }
+                ListItem::new(format!(
+                    "{} {}  {}",
+                    marker,
+                    model.name,
+                    details.join(" · ")
+                ))
+            })
+            .collect::<Vec<ListItem>>();
+
+        let status = match &self.pull {
+            Some(progress) => progress.to_string(),
+            None if self.loading => t!("model_picker_loading").to_string(),
+            None => String::new(),
+        };
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" {} ", t!("model_picker_title")))
+                    .title_style(Style::default().bold())
+                    .title_alignment(Alignment::Center)
+                    .title_bottom(Line::from(status).centered())
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Green)),
+            )
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.state);
+    }
+}
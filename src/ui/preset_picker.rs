@@ -0,0 +1,74 @@
+use super::*;
+
+/// Popup listing the prompt presets, to choose the one of a conversation.
+#[derive(Debug, Default, Clone)]
+pub struct PresetPicker {
+    state: ListState,
+    pub presets: Vec<String>,
+}
+
+impl PresetPicker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows `presets` with the active one selected.
+    pub fn open(&mut self, presets: Vec<String>, active: &str) {
+        let selected = presets.iter().position(|name| name == active);
+        self.presets = presets;
+        self.state.select(selected.or(Some(0)));
+    }
+
+    pub fn selected(&self) -> Option<String> {
+        self.state
+            .selected()
+            .and_then(|i| self.presets.get(i))
+            .cloned()
+    }
+
+    pub fn scroll_down(&mut self) {
+        if self.presets.is_empty() {
+            return;
+        }
+        let i = match self.state.selected() {
+            Some(i) if i < self.presets.len() - 1 => i + 1,
+            Some(i) => i,
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    pub fn scroll_up(&mut self) {
+        let i = match self.state.selected() {
+            Some(i) => i.saturating_sub(1),
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    pub fn render(&mut self, frame: &mut Frame, area: Rect, active: &str) {
+        let items = self
+            .presets
+            .iter()
+            .map(|name| {
+                let marker = if name == active { "●" } else { " " };
+                ListItem::new(format!("{} {}", marker, name))
+            })
+            .collect::<Vec<ListItem>>();
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" {} ", t!("preset_picker_title")))
+                    .title_style(Style::default().bold())
+                    .title_alignment(Alignment::Center)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Green)),
+            )
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.state);
+    }
+}
@@ -8,9 +8,6 @@ use unicode_width::UnicodeWidthStr;
 use crate::app::FocusedBlock;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use std::time::{Duration, Instant};
-use tokio::time::interval;
-
 #[derive(Debug, PartialEq)]
 pub enum Mode {
     Normal,
@@ -59,6 +56,12 @@ impl Prompt<'_> {
         self.editor.cut();
     }
 
+    /// Replaces the text being edited.
+    pub fn set_text(&mut self, text: &str) {
+        self.clear();
+        self.editor.insert_str(text);
+    }
+
     pub fn height(&self, frame_size: &Rect) -> u16 {
         let prompt_block_max_height = (0.4 * frame_size.height as f32) as u16;
 
@@ -214,10 +217,8 @@ impl Prompt<'_> {
 
                 KeyCode::Char('G') => self.editor.move_cursor(CursorMove::Bottom),
 
-                KeyCode::Char('g') => {
-                    if previous_key == KeyCode::Char('g') {
-                        self.editor.move_cursor(CursorMove::Jump(0, 0))
-                    }
+                KeyCode::Char('g') if previous_key == KeyCode::Char('g') => {
+                    self.editor.move_cursor(CursorMove::Jump(0, 0))
                 }
 
                 KeyCode::Char('D') => {
@@ -226,20 +227,16 @@ impl Prompt<'_> {
                     self.editor.delete_line_by_head();
                 }
 
-                KeyCode::Char('d') => {
-                    if previous_key == KeyCode::Char('d') {
-                        self.editor.move_cursor(CursorMove::Head);
-                        self.editor.delete_line_by_end();
-                    }
+                KeyCode::Char('d') if previous_key == KeyCode::Char('d') => {
+                    self.editor.move_cursor(CursorMove::Head);
+                    self.editor.delete_line_by_end();
                 }
 
-                KeyCode::Char('c') => {
-                    if previous_key == KeyCode::Char('c') {
-                        self.editor.move_cursor(CursorMove::Head);
-                        self.editor.delete_line_by_end();
-                        self.mode = Mode::Insert;
-                        self.update(&FocusedBlock::Prompt);
-                    }
+                KeyCode::Char('c') if previous_key == KeyCode::Char('c') => {
+                    self.editor.move_cursor(CursorMove::Head);
+                    self.editor.delete_line_by_end();
+                    self.mode = Mode::Insert;
+                    self.update(&FocusedBlock::Prompt);
                 }
 
                 KeyCode::Char('C') => {
@@ -294,11 +291,10 @@ impl Prompt<'_> {
                 }
 
                 KeyCode::Char('p') => {
-                    if !self.editor.paste() {
-                        if let Some(clipboard) = clipboard {
-                            if let Ok(text) = clipboard.get_text() {
-                                self.editor.insert_str(text);
-                            }
+                    let pasted = self.editor.paste();
+                    if !pasted {
+                        if let Some(Ok(text)) = clipboard.map(|c| c.get_text()) {
+                            self.editor.insert_str(text);
                         }
                     }
                 }
@@ -316,5 +312,4 @@ impl Prompt<'_> {
         self.editor.set_block(self.block.clone());
         frame.render_widget(self.editor.widget(), block);
     }
-
 }
@@ -0,0 +1,208 @@
+use super::*;
+
+use std::collections::VecDeque;
+
+use crate::llm::Message;
+
+/// Prompts submitted while an answer streams, sent in order once it ends.
+/// Shown under the chat, and as a popup to edit or remove them beforehand.
+#[derive(Debug, Default, Clone)]
+pub struct PromptQueue {
+    state: ListState,
+    prompts: VecDeque<Message>,
+    /// Where the prompt taken back for editing goes when resubmitted.
+    editing: Option<usize>,
+}
+
+impl PromptQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, message: Message) {
+        self.prompts.push_back(message);
+    }
+
+    /// Queues `message` at the place of the prompt taken out for editing, last
+    /// when no edit is pending.
+    pub fn reinsert(&mut self, message: Message) {
+        match self.editing.take() {
+            Some(i) => self.prompts.insert(i.min(self.prompts.len()), message),
+            None => self.prompts.push_back(message),
+        }
+    }
+
+    /// Forgets the place of the prompt taken out for editing.
+    pub fn abandon_edit(&mut self) {
+        self.editing = None;
+    }
+
+    /// The next prompt to send.
+    pub fn pop(&mut self) -> Option<Message> {
+        let message = self.prompts.pop_front()?;
+        self.editing = self.editing.map(|i| i.saturating_sub(1));
+        Some(message)
+    }
+
+    pub fn clear(&mut self) {
+        self.prompts.clear();
+        self.editing = None;
+    }
+
+    pub fn open(&mut self) {
+        self.state.select((!self.prompts.is_empty()).then_some(0));
+    }
+
+    /// Removes the highlighted prompt.
+    pub fn remove(&mut self) -> Option<Message> {
+        let i = self.state.selected()?;
+        let message = self.prompts.remove(i)?;
+        if let Some(editing) = self.editing.as_mut() {
+            if *editing > i {
+                *editing -= 1;
+            }
+        }
+        if i >= self.prompts.len() {
+            self.state.select(self.prompts.len().checked_sub(1));
+        }
+        Some(message)
+    }
+
+    /// Takes the highlighted prompt out to be edited, its place is kept.
+    pub fn edit(&mut self) -> Option<Message> {
+        let i = self.state.selected()?;
+        let message = self.remove()?;
+        self.editing = Some(i);
+        Some(message)
+    }
+
+    pub fn scroll_down(&mut self) {
+        if self.prompts.is_empty() {
+            return;
+        }
+        let i = match self.state.selected() {
+            Some(i) if i < self.prompts.len() - 1 => i + 1,
+            Some(i) => i,
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    pub fn scroll_up(&mut self) {
+        let i = match self.state.selected() {
+            Some(i) => i.saturating_sub(1),
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    /// Lines appended under the chat.
+    pub fn chat_lines(&self) -> Vec<Line<'static>> {
+        self.prompts
+            .iter()
+            .map(|message| {
+                Line::styled(
+                    format!(
+                        "⏳ {}: {}",
+                        t!("queued_prompt"),
+                        first_line(&message.content)
+                    ),
+                    Style::default().fg(Color::DarkGray),
+                )
+            })
+            .collect()
+    }
+
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        let items = self
+            .prompts
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                let pin = if message.pinned { "📌 " } else { "" };
+                ListItem::new(format!(
+                    "{}. {}{}",
+                    i + 1,
+                    pin,
+                    first_line(&message.content)
+                ))
+            })
+            .collect::<Vec<ListItem>>();
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" {} ", t!("queue_title")))
+                    .title_style(Style::default().bold())
+                    .title_alignment(Alignment::Center)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Green)),
+            )
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.state);
+    }
+}
+
+fn first_line(text: &str) -> String {
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or_default();
+    if lines.next().is_some() {
+        format!("{} …", first)
+    } else {
+        first.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::llm::LLMRole;
+
+    fn queue(prompts: &[&str]) -> PromptQueue {
+        let mut queue = PromptQueue::new();
+        for prompt in prompts {
+            queue.push(Message::new(LLMRole::USER, prompt.to_string()));
+        }
+        queue
+    }
+
+    fn contents(queue: &PromptQueue) -> Vec<&str> {
+        queue.prompts.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn edited_prompt_keeps_its_place() {
+        let mut queue = queue(&["first", "second", "third"]);
+        queue.open();
+        queue.scroll_down();
+
+        let mut edited = queue.edit().unwrap();
+        assert_eq!(contents(&queue), ["first", "third"]);
+
+        // The first one is sent meanwhile
+        assert_eq!(queue.pop().unwrap().content, "first");
+        edited.content = "second, edited".to_string();
+        queue.reinsert(edited);
+        assert_eq!(contents(&queue), ["second, edited", "third"]);
+
+        queue.open();
+        queue.remove();
+        assert_eq!(contents(&queue), ["third"]);
+    }
+
+    #[test]
+    fn abandoned_edit_leaves_later_prompts_in_order() {
+        let mut queue = queue(&["first", "second"]);
+        queue.open();
+        queue.edit().unwrap();
+
+        queue.abandon_edit();
+        queue.reinsert(Message::new(LLMRole::USER, "third".to_string()));
+        queue.push(Message::new(LLMRole::USER, "fourth".to_string()));
+        assert_eq!(contents(&queue), ["second", "third", "fourth"]);
+    }
+}
@@ -8,8 +8,6 @@ use ratatui::Terminal;
 use std::io;
 use std::panic;
 
-use std::sync::{Arc, Mutex};
-
 #[derive(Debug)]
 pub struct Tui<B: Backend> {
     terminal: Terminal<B>,
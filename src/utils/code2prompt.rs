@@ -6,7 +6,6 @@ use arboard::Clipboard;
 use git2::{DiffOptions, Repository};
 use handlebars::{no_escape, Handlebars};
 use ignore::WalkBuilder;
-use regex::Regex;
 use serde_json::json;
 use termtree::Tree;
 
@@ -26,14 +25,6 @@ pub struct Code2Prompt {
     /// Optional comma-separated list of folder paths to exclude
     exclude_folders: Option<String>,
 
-    /// Display the token count of the generated prompt
-    tokens: bool,
-
-    /// Optional tokenizer to use for token count
-    ///
-    /// Supported tokenizers: cl100k (default), p50k, p50k_edit, r50k, gpt2
-    encoding: Option<String>,
-
     diff: bool,
 
     line_number: bool,
@@ -47,8 +38,6 @@ impl Default for Code2Prompt {
             exclude: Default::default(),
             exclude_files: Default::default(),
             exclude_folders: Default::default(),
-            tokens: Default::default(),
-            encoding: Default::default(),
             diff: Default::default(),
             line_number: Default::default(),
         }
@@ -56,9 +45,9 @@ impl Default for Code2Prompt {
 }
 
 impl Code2Prompt {
-    pub fn new(path: &PathBuf) -> Self {
+    pub fn new(path: &Path) -> Self {
         Self {
-            path: path.clone(),
+            path: path.to_path_buf(),
             ..Default::default()
         }
     }
@@ -83,7 +72,7 @@ Source Tree:
 {{/each}}
 "#;
 
-pub fn code2prompt(path: &PathBuf) -> Result<()> {
+pub fn code2prompt(path: &Path) -> Result<()> {
     let args = Code2Prompt::new(path);
     let mut handlebars = Handlebars::new();
     handlebars.register_escape_fn(no_escape);
@@ -122,17 +111,6 @@ pub fn code2prompt(path: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-/// Extracts undefined variable names from the template string.
-#[inline]
-fn extract_undefined_variables(template: &str) -> Vec<String> {
-    let registered_identifiers = vec!["path", "code", "git_diff"];
-    let re = Regex::new(r"\{\{\s*(?P<var>[a-zA-Z_][a-zA-Z_0-9]*)\s*\}\}").unwrap();
-    re.captures_iter(template)
-        .map(|cap| cap["var"].to_string())
-        .filter(|var| !registered_identifiers.contains(&var.as_str()))
-        .collect()
-}
-
 /// Wraps the code block with backticks and adds the file extension as a label
 #[inline]
 fn wrap_code_block(code: &str, extension: &str, line_numbers: bool) -> String {
@@ -170,8 +148,8 @@ fn label<P: AsRef<Path>>(p: P) -> String {
 }
 
 /// Traverses the directory, builds the tree, and collects information about each file.
-fn traverse_directory(
-    root_path: &PathBuf,
+pub(crate) fn traverse_directory(
+    root_path: &Path,
     filter: &Option<String>,
     exclude: &Option<String>,
     exclude_files: &Option<String>,
@@ -250,7 +228,9 @@ fn traverse_directory(
                         }
                     }
 
-                    let code_bytes = fs::read(&path).expect("Failed to read file");
+                    let Ok(code_bytes) = fs::read(path) else {
+                        return root;
+                    };
                     let code = String::from_utf8_lossy(&code_bytes);
 
                     let code_block = wrap_code_block(&code, extension, line_number);
@@ -271,7 +251,7 @@ fn traverse_directory(
     Ok((tree.to_string(), files))
 }
 
-fn get_git_diff(repo_path: &Path) -> Result<String, git2::Error> {
+pub(crate) fn get_git_diff(repo_path: &Path) -> Result<String, git2::Error> {
     let repo = Repository::open(repo_path)?;
     let head = repo.head()?;
     let head_tree = head.peel_to_tree()?;